@@ -0,0 +1,22 @@
+use crowser::{error::CrowserError, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+
+  // Stays the same even though example.com sets its own title
+  window.set_title("My Crowser App")?;
+  window.set_icon(std::env::current_dir()?.join("examples/icon.svg"))?;
+
+  window.clear_profile().unwrap_or_default();
+
+  window.create()?;
+
+  Ok(())
+}
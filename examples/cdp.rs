@@ -0,0 +1,39 @@
+use crowser::{error::CrowserError, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+  let ipc = window.ipc();
+
+  window.clear_profile().unwrap_or_default();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    // Anything Crowser doesn't wrap can be sent directly
+    let version = ipc
+      .cdp_send_browser("Browser.getVersion", serde_json::json!({}))
+      .unwrap();
+    println!("Browser version: {:?}", version["product"]);
+
+    // Domains usually have to be enabled before they emit events
+    let requests = ipc.cdp_subscribe("Network.requestWillBeSent").unwrap();
+    ipc
+      .cdp_send("Network.enable", serde_json::json!({}))
+      .unwrap();
+
+    for event in requests.iter() {
+      println!("Request: {:?}", event.params["request"]["url"]);
+    }
+  });
+
+  window.create()?;
+
+  Ok(())
+}
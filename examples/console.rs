@@ -0,0 +1,46 @@
+use crowser::{error::CrowserError, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+  let ipc = window.ipc();
+
+  window.on_console(|message| {
+    println!("[{:?}] {}", message.level, message.text);
+  })?;
+
+  window.on_exception(|exception| {
+    println!("Uncaught {}", exception.message);
+
+    for frame in &exception.stack {
+      println!(
+        "  at {} ({}:{}:{})",
+        frame.function_name, frame.url, frame.line, frame.column
+      );
+    }
+  })?;
+
+  window.clear_profile().unwrap_or_default();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    ipc
+      .eval("console.log('Hello from the page', { answer: 42 }, [1, 2, 3])")
+      .unwrap_or_default();
+    ipc.eval("console.warn('Careful now')").unwrap_or_default();
+    ipc
+      .eval("setTimeout(() => { throw new TypeError('Something broke') }, 0)")
+      .unwrap_or_default();
+  });
+
+  window.create()?;
+
+  Ok(())
+}
@@ -0,0 +1,43 @@
+use crowser::{error::CrowserError, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+  window.center()?;
+  window.set_min_size(400, 300)?;
+  window.set_max_size(1280, 960)?;
+
+  // Reopen wherever the window was last closed. `center()` only applies on the first launch
+  window.set_remember_geometry(true)?;
+
+  let ipc = window.ipc();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    println!("Window bounds: {:?}", ipc.bounds());
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    ipc.resize(1024, 768).unwrap_or_default();
+    ipc.move_to(0, 0).unwrap_or_default();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    ipc.maximize().unwrap_or_default();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    ipc.set_fullscreen(true).unwrap_or_default();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    ipc.set_fullscreen(false).unwrap_or_default();
+  });
+
+  window.create()?;
+
+  Ok(())
+}
@@ -11,6 +11,9 @@ fn main() -> Result<(), CrowserError> {
   let mut window = Window::new(config, None, profile_dir)?;
   window.set_initialization_script("window.alert('Hello from Crowser!')")?;
 
+  // Initialization scripts run before the page's own scripts, and after Crowser's IPC is set up
+  window.add_initialization_script("console.log('IPC available:', !!window.__CROWSER)")?;
+
   window.clear_profile().unwrap_or_default();
 
   window.create()?;
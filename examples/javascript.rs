@@ -19,6 +19,17 @@ fn main() -> Result<(), CrowserError> {
     let two = ipc.eval("1 + 1").unwrap();
     println!("1 + 1 = {:?}", two);
 
+    let (width, height): (u32, u32) = ipc
+      .eval_typed("[innerWidth, innerHeight]")
+      .unwrap_or_default();
+    println!("The page is {}x{}", width, height);
+
+    // Arguments are passed as values, never pasted into the script
+    let title = "Crowser's \"JavaScript\" example";
+    ipc
+      .call_function("(title) => { document.title = title }", (title,))
+      .unwrap_or_default();
+
     std::thread::sleep(std::time::Duration::from_secs(2));
 
     ipc.eval("alert('Hello from Crowser!')").unwrap_or_default();
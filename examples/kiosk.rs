@@ -0,0 +1,25 @@
+use crowser::{error::CrowserError, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+
+  // Pass "frameless" to get a normal window with only the title bar and the page instead (Chromium windows already are).
+  // Kiosk mode has no way out except closing the app (eg. with Ctrl+C in the terminal)
+  match std::env::args().nth(1).as_deref() {
+    Some("frameless") => window.set_frameless(true)?,
+    _ => window.set_kiosk(true)?,
+  }
+
+  window.clear_profile().unwrap_or_default();
+
+  window.create()?;
+
+  Ok(())
+}
@@ -0,0 +1,50 @@
+use crowser::{error::CrowserError, NavigationAction, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+
+  // The window can stay on example.com and go to rust-lang.org. Links anywhere else go to the default browser,
+  // except for IANA's, which don't go anywhere at all
+  window.allow_navigation("https://www.rust-lang.org")?;
+  window.on_navigation(|url| {
+    println!("Navigation to {}", url);
+
+    match url.contains("iana.org") {
+      true => NavigationAction::Deny,
+      false => NavigationAction::OpenExternal,
+    }
+  })?;
+
+  let ipc = window.ipc();
+
+  window.clear_profile().unwrap_or_default();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    ipc.navigate("https://www.rust-lang.org").unwrap();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    println!("Now at {}", ipc.current_url().unwrap_or_default());
+
+    ipc.go_back().unwrap();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    println!("Back at {}", ipc.current_url().unwrap_or_default());
+
+    ipc.go_forward().unwrap();
+    ipc.reload(true).unwrap();
+  });
+
+  window.create()?;
+
+  Ok(())
+}
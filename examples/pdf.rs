@@ -0,0 +1,42 @@
+use crowser::{error::CrowserError, PdfOptions, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+  let ipc = window.ipc();
+
+  window.clear_profile().unwrap_or_default();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    // Give the page a moment to finish loading
+    std::thread::sleep(std::time::Duration::from_secs(2));
+
+    let options = PdfOptions {
+      print_background: true,
+      footer_template: Some(
+        "<div style='font-size: 10px; margin: auto'><span class='pageNumber'></span> / <span class='totalPages'></span></div>"
+          .to_string(),
+      ),
+      ..Default::default()
+    };
+
+    let pdf = ipc.print_to_pdf(options).unwrap();
+    std::fs::write("page.pdf", pdf).unwrap();
+    println!("Saved page.pdf");
+
+    // Close once done
+    ipc.close().unwrap_or_default();
+  });
+
+  window.create()?;
+
+  Ok(())
+}
@@ -0,0 +1,50 @@
+use crowser::{error::CrowserError, PopupAction, RemoteConfig, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+  let ipc = window.ipc();
+
+  // Keep popups to example.com as app windows, and send everything else to the default browser
+  window.on_popup(|url| match url.starts_with("https://example.com") {
+    true => PopupAction::OpenWindow,
+    false => PopupAction::OpenExternal,
+  })?;
+
+  window.on_popup_window(|popup| {
+    println!(
+      "Popup opened at {}",
+      popup.current_url().unwrap_or_default()
+    );
+
+    popup
+      .register_command("hello", |_| {
+        println!("Hello from the popup!");
+        Ok(serde_json::Value::Null)
+      })
+      .unwrap_or_default();
+  })?;
+
+  window.clear_profile().unwrap_or_default();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    ipc
+      .eval("window.open('https://example.com/?popup')")
+      .unwrap_or_default();
+    ipc
+      .eval("window.open('https://www.rust-lang.org')")
+      .unwrap_or_default();
+  });
+
+  window.create()?;
+
+  Ok(())
+}
@@ -0,0 +1,44 @@
+use crowser::{error::CrowserError, ClipRect, RemoteConfig, ScreenshotFormat, Window};
+
+fn main() -> Result<(), CrowserError> {
+  let mut profile_dir = std::env::current_dir()?;
+  profile_dir.push("example_profiles");
+
+  let config = RemoteConfig {
+    url: "https://example.com".to_string(),
+  };
+
+  let mut window = Window::new(config, None, profile_dir)?;
+  let ipc = window.ipc();
+
+  window.clear_profile().unwrap_or_default();
+
+  std::thread::spawn(move || {
+    ipc.block_until_initialized().unwrap_or_default();
+
+    // Give the page a moment to finish loading
+    std::thread::sleep(std::time::Duration::from_secs(2));
+
+    let page = ipc.screenshot(ScreenshotFormat::Png, None, true).unwrap();
+    std::fs::write("screenshot.png", page).unwrap();
+
+    let corner = ClipRect {
+      x: 0.0,
+      y: 0.0,
+      width: 200.0,
+      height: 100.0,
+    };
+    let corner = ipc
+      .screenshot(ScreenshotFormat::Jpeg, Some(corner), false)
+      .unwrap();
+    std::fs::write("screenshot.jpg", corner).unwrap();
+
+    println!("Saved screenshot.png and screenshot.jpg");
+
+    ipc.close().unwrap_or_default();
+  });
+
+  window.create()?;
+
+  Ok(())
+}
@@ -0,0 +1,43 @@
+/**
+ * Keeps the window title and icon fixed to what the app asked for, no matter what the page does.
+ * Called with the title and icon URL, either of which may be null to leave it alone.
+ */
+(title, icon) => {
+  // Only the top frame decides what the window looks like
+  if (window.top !== window) return
+
+  const titleDescriptor = Object.getOwnPropertyDescriptor(Document.prototype, "title")
+
+  const apply = () => {
+    if (title !== null && titleDescriptor.get.call(document) !== title) {
+      titleDescriptor.set.call(document, title)
+    }
+
+    if (icon !== null && document.head) {
+      for (const link of document.head.querySelectorAll("link[rel~='icon']")) {
+        if (!link.hasAttribute("data-crowser")) link.remove()
+      }
+
+      if (!document.head.querySelector("link[data-crowser]")) {
+        const link = document.createElement("link")
+        link.rel = "icon"
+        link.href = icon
+        link.setAttribute("data-crowser", "")
+        document.head.appendChild(link)
+      }
+    }
+  }
+
+  // The page's own attempts to change the title are ignored
+  if (title !== null) {
+    Object.defineProperty(document, "title", {
+      get: () => title,
+      set: () => {},
+      configurable: true,
+    })
+  }
+
+  // Pages (and frameworks) replace the <title> and <link> elements as they load, so keep putting them back
+  new MutationObserver(apply).observe(document, { subtree: true, childList: true, characterData: true })
+  apply()
+}
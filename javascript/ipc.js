@@ -17,11 +17,29 @@ if (!window.__CROWSER) {
     // id, cmd, args
     inbound_invokes: {},
 
+    // Invokes the backend refused to run, or whose command failed
+    // id -> { message, name }
+    rejected_invokes: {},
+
+    // Invokes that timed out or were aborted after the backend picked them up, whose responses are dropped
+    // id -> true
+    cancelled_invokes: {},
+
     // Master message queue to consume from
     // id, type, payload
     message_queue: [],
 
-    invoke: (cmd, args = {}) => {
+    /**
+     * Invoke a command registered on the Rust side, and resolve with its response.
+     *
+     * `options.timeout` is in milliseconds, after which the promise rejects with a `TimeoutError`.
+     * `options.signal` is an `AbortSignal`, which rejects the promise with its reason when aborted.
+     * Either way, a command the backend hasn't picked up yet never runs, and a late response is ignored.
+     *
+     * Commands this frame's origin isn't allowed to invoke reject with a `NotAllowedError`, commands that aren't registered
+     * with a `NotFoundError`, and commands that return an error with an `OperationError` carrying its message.
+     */
+    invoke: (cmd, args = {}, options = {}) => {
       if (cmd === "") {
         console.error("[Crowser IPC] Empty command")
         return
@@ -32,16 +50,58 @@ if (!window.__CROWSER) {
         return
       }
 
+      const { timeout, signal } = options
+
+      if (signal && signal.aborted) {
+        return Promise.reject(abortReason(signal))
+      }
+
       let uuid = generateUUID()
-      window.__CROWSER.ipc.outbound_invokes.push({ uuid, cmd, args })
+      const request = { uuid, cmd, args }
+      window.__CROWSER.ipc.outbound_invokes.push(request)
+
+      const cancel = () => {
+        const index = window.__CROWSER.ipc.outbound_invokes.indexOf(request)
+
+        if (index === -1) {
+          window.__CROWSER.ipc.cancelled_invokes[uuid] = true
+        } else {
+          window.__CROWSER.ipc.outbound_invokes.splice(index, 1)
+        }
+      }
 
       // Wait for a response
       return new Promise(async (resolve, reject) => {
+        const started = Date.now()
+
         while (window.__CROWSER.ipc.inbound_invokes[uuid] === undefined) {
+          const rejection = window.__CROWSER.ipc.rejected_invokes[uuid]
+
+          if (rejection !== undefined) {
+            delete window.__CROWSER.ipc.rejected_invokes[uuid]
+            reject(new DOMException(rejection.message, rejection.name))
+            return
+          }
+
+          if (signal && signal.aborted) {
+            cancel()
+            reject(abortReason(signal))
+            return
+          }
+
+          if (timeout !== undefined && Date.now() - started >= timeout) {
+            cancel()
+            reject(new DOMException(`Command "${cmd}" timed out after ${timeout}ms`, "TimeoutError"))
+            return
+          }
+
           await wait(5)
         }
 
-        resolve(window.__CROWSER.ipc.inbound_invokes[uuid])
+        const result = window.__CROWSER.ipc.inbound_invokes[uuid]
+        delete window.__CROWSER.ipc.inbound_invokes[uuid]
+
+        resolve(result)
       })
     },
     event: {
@@ -96,7 +156,21 @@ if (!window.__CROWSER) {
     },
 
     _backend_respond: (uuid, result) => {
+      if (window.__CROWSER.ipc.cancelled_invokes[uuid]) {
+        delete window.__CROWSER.ipc.cancelled_invokes[uuid]
+        return
+      }
+
       window.__CROWSER.ipc.inbound_invokes[uuid] = result
+    },
+
+    _backend_reject: (uuid, message, name) => {
+      if (window.__CROWSER.ipc.cancelled_invokes[uuid]) {
+        delete window.__CROWSER.ipc.cancelled_invokes[uuid]
+        return
+      }
+
+      window.__CROWSER.ipc.rejected_invokes[uuid] = { message, name }
     }
   }
 
@@ -107,6 +181,11 @@ function wait(ms) {
   return new Promise((r) => setTimeout(r, ms))
 }
 
+// Older browsers don't give aborted signals a reason
+function abortReason(signal) {
+  return signal.reason !== undefined ? signal.reason : new DOMException("The command was aborted", "AbortError")
+}
+
 // https://stackoverflow.com/a/8809472/13438741
 function generateUUID() {
   var d = new Date().getTime();//Timestamp
@@ -0,0 +1,482 @@
+use std::{sync::Arc, time::Duration};
+
+use serde_json::Value;
+
+use super::{
+  wait_for_url, Backend, BackendEvent, ConsoleEvent, ConsoleHandler, EventFilter, ExecutionContext,
+  NavigationHandler, Popup, PopupHandler, CAPTURE_TIMEOUT,
+};
+use crate::{
+  bidi::{json_to_local_value, remote_value_to_json, Bidi},
+  console::{ConsoleLevel, ConsoleMessage, PageException, StackFrame},
+  error::CrowserError,
+  util, ClipRect, PdfOptions, ScreenshotFormat, WindowBounds, WindowState,
+};
+
+impl Backend for Bidi {
+  fn evaluate(
+    &self,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    let result = self.send(
+      "script.evaluate",
+      serde_json::json!({
+        "expression": script,
+        "target": { "context": self.context() },
+        "awaitPromise": true,
+        "resultOwnership": "none",
+      }),
+      timeout,
+    )?;
+
+    script_result(&result)
+  }
+
+  fn evaluate_in(
+    &self,
+    context: &ExecutionContext,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    let result = self.send(
+      "script.evaluate",
+      serde_json::json!({
+        "expression": script,
+        "target": { "realm": context.id },
+        "awaitPromise": true,
+        "resultOwnership": "none",
+      }),
+      timeout,
+    )?;
+
+    script_result(&result)
+  }
+
+  fn execution_contexts(&self) -> Vec<ExecutionContext> {
+    self.realms()
+  }
+
+  fn call_function(
+    &self,
+    function: &str,
+    args: Vec<Value>,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    let result = self.send(
+      "script.callFunction",
+      serde_json::json!({
+        "functionDeclaration": function,
+        "arguments": args.iter().map(json_to_local_value).collect::<Vec<_>>(),
+        "target": { "context": self.context() },
+        "awaitPromise": true,
+        "resultOwnership": "none",
+      }),
+      timeout,
+    )?;
+
+    script_result(&result)
+  }
+
+  fn add_preload_script(&self, script: &str) -> Result<String, CrowserError> {
+    // Preload scripts run before anything on the page, in every frame. Other windows (popups) get their own
+    let result = self.send(
+      "script.addPreloadScript",
+      serde_json::json!({
+        "functionDeclaration": preload_function(script),
+        "contexts": [self.context()],
+      }),
+      None,
+    )?;
+
+    Ok(result["script"].as_str().unwrap_or_default().to_string())
+  }
+
+  fn navigate(&self, url: &str) -> Result<(), CrowserError> {
+    self.send(
+      "browsingContext.navigate",
+      serde_json::json!({ "context": self.context(), "url": url, "wait": "none" }),
+      None,
+    )?;
+
+    Ok(())
+  }
+
+  fn reload(&self, ignore_cache: bool) -> Result<(), CrowserError> {
+    self.send(
+      "browsingContext.reload",
+      serde_json::json!({ "context": self.context(), "ignoreCache": ignore_cache, "wait": "none" }),
+      None,
+    )?;
+
+    Ok(())
+  }
+
+  fn traverse_history(&self, delta: i64) -> Result<(), CrowserError> {
+    self.send(
+      "browsingContext.traverseHistory",
+      serde_json::json!({ "context": self.context(), "delta": delta }),
+      None,
+    )?;
+
+    Ok(())
+  }
+
+  fn current_url(&self) -> Result<String, CrowserError> {
+    let tree = self.send(
+      "browsingContext.getTree",
+      serde_json::json!({ "root": self.context(), "maxDepth": 0 }),
+      None,
+    )?;
+
+    Ok(
+      tree["contexts"][0]["url"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string(),
+    )
+  }
+
+  fn subscribe(&self, event: BackendEvent) -> Result<flume::Receiver<Value>, CrowserError> {
+    let context = self.context().to_string();
+    let (method, filter): (&str, EventFilter) = match event {
+      BackendEvent::Closed => (
+        "browsingContext.contextDestroyed",
+        Box::new(move |params| params["context"] == context.as_str()),
+      ),
+    };
+
+    let rx = self.subscribe(method)?;
+    // A receiver that falls behind backs up into the connection's queue for `rx`, which then drops events as usual
+    let (tx, params_rx) = flume::bounded(1);
+
+    // Forward only the events that are actually about our browsing context. Ends when either side goes away
+    std::thread::spawn(move || {
+      for evt in rx.iter() {
+        if filter(&evt.params) && tx.send(evt.params).is_err() {
+          break;
+        }
+      }
+    });
+
+    Ok(params_rx)
+  }
+
+  fn intercept_navigation(&self, handler: NavigationHandler) -> Result<(), CrowserError> {
+    let rx = self.subscribe_in("network.beforeRequestSent", self.context())?;
+
+    // Intercepts can't be limited to documents, so every request in the window is paused and most are let straight through
+    let intercept = self.send(
+      "network.addIntercept",
+      serde_json::json!({ "phases": ["beforeRequestSent"], "contexts": [self.context()] }),
+      None,
+    )?["intercept"]
+      .clone();
+
+    let bidi = self.clone();
+
+    std::thread::spawn(move || {
+      for event in rx.iter() {
+        let params = &event.params;
+
+        // Popups share the connection and have intercepts of their own, whose requests are theirs to decide on.
+        // Requests from our iframes are paused by our intercept too, so they're let through below
+        let ours = params["intercepts"]
+          .as_array()
+          .is_some_and(|intercepts| intercepts.contains(&intercept));
+
+        if params["isBlocked"] != true || !ours {
+          continue;
+        }
+
+        let request = params["request"]["request"].clone();
+        let is_navigation = !params["navigation"].is_null() && params["context"] == bidi.context();
+        let allowed =
+          !is_navigation || handler(params["request"]["url"].as_str().unwrap_or_default());
+
+        let method = match allowed {
+          true => "network.continueRequest",
+          false => "network.failRequest",
+        };
+
+        bidi
+          .send(method, serde_json::json!({ "request": request }), None)
+          .unwrap_or_default();
+      }
+    });
+
+    Ok(())
+  }
+
+  fn watch_popups(&self, handler: PopupHandler) -> Result<(), CrowserError> {
+    let rx = self.subscribe("browsingContext.contextCreated")?;
+    let bidi = self.clone();
+
+    std::thread::spawn(move || {
+      for event in rx.iter() {
+        let params = &event.params;
+
+        // Windows that don't say who opened them aren't assumed to be ours, since they may be anyone's popups
+        if !params["parent"].is_null() || params["originalOpener"] != bidi.context() {
+          continue;
+        }
+
+        let popup = bidi.for_context(
+          params["context"].as_str().unwrap_or_default(),
+          params["clientWindow"].as_str().unwrap_or_default(),
+        );
+
+        handler(Popup {
+          url: wait_for_url(&popup),
+          backend: Arc::new(popup),
+        });
+      }
+    });
+
+    Ok(())
+  }
+
+  fn watch_console(&self, handler: ConsoleHandler) -> Result<(), CrowserError> {
+    let rx = self.subscribe("log.entryAdded")?;
+    let context = self.context().to_string();
+
+    std::thread::spawn(move || {
+      for event in rx.iter() {
+        let params = &event.params;
+
+        if params["source"]["context"] != context.as_str() {
+          continue;
+        }
+
+        let text = params["text"].as_str().unwrap_or_default().to_string();
+        let stack = StackFrame::from_stack_trace(&params["stackTrace"]);
+
+        // Besides "console" and "javascript" (uncaught exceptions), browsers may add entry types of their own
+        match params["type"].as_str() {
+          Some("console") => handler(ConsoleEvent::Message(ConsoleMessage {
+            level: match (params["level"].as_str(), params["method"].as_str()) {
+              (Some("debug"), _) => ConsoleLevel::Debug,
+              (Some("warn"), _) => ConsoleLevel::Warn,
+              (Some("error"), _) => ConsoleLevel::Error,
+              (_, Some("info")) => ConsoleLevel::Info,
+              _ => ConsoleLevel::Log,
+            },
+            text,
+            args: params["args"]
+              .as_array()
+              .into_iter()
+              .flatten()
+              .map(remote_value_to_json)
+              .collect(),
+            stack,
+          })),
+          Some("javascript") => {
+            // Log entries only say where the error happened through their stack
+            let location = stack.first().cloned().unwrap_or(StackFrame {
+              function_name: String::new(),
+              url: String::new(),
+              line: 0,
+              column: 0,
+            });
+
+            handler(ConsoleEvent::Exception(PageException {
+              message: text,
+              url: location.url,
+              line: location.line,
+              column: location.column,
+              stack,
+            }))
+          }
+          _ => {}
+        }
+      }
+    });
+
+    Ok(())
+  }
+
+  fn screenshot(
+    &self,
+    format: ScreenshotFormat,
+    clip: Option<ClipRect>,
+    full_page: bool,
+  ) -> Result<Vec<u8>, CrowserError> {
+    let mime = match format {
+      ScreenshotFormat::Png => "image/png",
+      ScreenshotFormat::Jpeg => "image/jpeg",
+      ScreenshotFormat::Webp => "image/webp",
+    };
+
+    let mut params = serde_json::json!({
+      "context": self.context(),
+      "origin": if full_page || clip.is_some() { "document" } else { "viewport" },
+      "format": { "type": mime },
+    });
+
+    if let Some(clip) = clip {
+      params["clip"] = serde_json::json!({
+        "type": "box",
+        "x": clip.x,
+        "y": clip.y,
+        "width": clip.width,
+        "height": clip.height,
+      });
+    }
+
+    let result = self.send(
+      "browsingContext.captureScreenshot",
+      params,
+      Some(CAPTURE_TIMEOUT),
+    )?;
+
+    util::base64::decode(result["data"].as_str().unwrap_or_default())
+      .ok_or_else(|| CrowserError::BidiError("Invalid screenshot data".to_string()))
+  }
+
+  fn print_to_pdf(&self, options: PdfOptions) -> Result<Vec<u8>, CrowserError> {
+    // BiDi measures in centimeters, and has no header or footer templates
+    let cm = |inches: f64| inches * 2.54;
+
+    let result = self.send(
+      "browsingContext.print",
+      serde_json::json!({
+        "context": self.context(),
+        "background": options.print_background,
+        "orientation": if options.landscape { "landscape" } else { "portrait" },
+        "page": {
+          "width": cm(options.paper_width),
+          "height": cm(options.paper_height),
+        },
+        "margin": {
+          "top": cm(options.margin_top),
+          "bottom": cm(options.margin_bottom),
+          "left": cm(options.margin_left),
+          "right": cm(options.margin_right),
+        },
+      }),
+      Some(CAPTURE_TIMEOUT),
+    )?;
+
+    util::base64::decode(result["data"].as_str().unwrap_or_default())
+      .ok_or_else(|| CrowserError::BidiError("Invalid PDF data".to_string()))
+  }
+
+  fn window_bounds(&self) -> Result<WindowBounds, CrowserError> {
+    let result = self.send("browser.getClientWindows", serde_json::json!({}), None)?;
+    let windows = result["clientWindows"]
+      .as_array()
+      .cloned()
+      .unwrap_or_default();
+    let window = windows
+      .iter()
+      .find(|w| w["clientWindow"] == self.client_window())
+      .or(windows.first());
+
+    match window {
+      Some(window) => Ok(WindowBounds {
+        x: window["x"].as_i64().unwrap_or_default() as i32,
+        y: window["y"].as_i64().unwrap_or_default() as i32,
+        width: window["width"].as_u64().unwrap_or_default() as u32,
+        height: window["height"].as_u64().unwrap_or_default() as u32,
+        state: serde_json::from_value(window["state"].clone()).unwrap_or_default(),
+      }),
+      None => Err(CrowserError::BidiError(
+        "No client windows found".to_string(),
+      )),
+    }
+  }
+
+  fn set_window_bounds(&self, bounds: WindowBounds) -> Result<(), CrowserError> {
+    let params = match bounds.state {
+      WindowState::Normal => serde_json::json!({
+        "clientWindow": self.client_window(),
+        "state": bounds.state,
+        "x": bounds.x,
+        "y": bounds.y,
+        "width": bounds.width,
+        "height": bounds.height,
+      }),
+      _ => serde_json::json!({
+        "clientWindow": self.client_window(),
+        "state": bounds.state,
+      }),
+    };
+
+    self.send("browser.setClientWindowState", params, None)?;
+
+    Ok(())
+  }
+
+  fn set_viewport(&self, width: u32, height: u32) -> Result<(), CrowserError> {
+    self.send(
+      "browsingContext.setViewport",
+      serde_json::json!({
+        "context": self.context(),
+        "viewport": { "width": width, "height": height },
+      }),
+      None,
+    )?;
+
+    Ok(())
+  }
+
+  fn close(&self) -> Result<(), CrowserError> {
+    self.send(
+      "browsingContext.close",
+      serde_json::json!({ "context": self.context() }),
+      None,
+    )?;
+
+    Ok(())
+  }
+}
+
+/// Preload scripts are functions, but init scripts are written for global scope like CDP runs them, so top-level
+/// declarations become globals. An indirect `eval` runs the source there, and JSON is a valid JavaScript string literal.
+fn preload_function(script: &str) -> String {
+  format!("() => {{ (0, eval)({}) }}", Value::from(script))
+}
+
+/// The value of a `script.EvaluateResult`, with `undefined` as `None` and exceptions as `CrowserError::ScriptError`
+fn script_result(result: &Value) -> Result<Option<Value>, CrowserError> {
+  if result["type"] == "exception" {
+    let details = &result["exceptionDetails"];
+    // The details have the same location fields as a stack frame
+    let location = StackFrame::from_protocol(details);
+
+    return Err(CrowserError::ScriptError(PageException {
+      message: details["text"]
+        .as_str()
+        .unwrap_or("Uncaught exception")
+        .to_string(),
+      url: location.url,
+      line: location.line,
+      column: location.column,
+      stack: StackFrame::from_stack_trace(&details["stackTrace"]),
+    }));
+  }
+
+  match result["result"]["type"].as_str() {
+    Some("undefined") => Ok(None),
+    _ => Ok(Some(remote_value_to_json(&result["result"]))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn preload_scripts_run_in_global_scope() {
+    let script =
+      "var answer = 42\nfunction greet() { return \"hi\" }\nlet quote = '`${x}`' // </script>";
+    let function = preload_function(script);
+
+    // Top-level declarations only become globals if the source is evaluated by itself, not inlined into the function
+    let source = function
+      .strip_prefix("() => { (0, eval)(")
+      .and_then(|rest| rest.strip_suffix(") }"))
+      .unwrap();
+    assert_eq!(serde_json::from_str::<String>(source).unwrap(), script);
+  }
+}
@@ -0,0 +1,1214 @@
+use std::{
+  collections::HashMap,
+  path::Path,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use super::{
+  Backend, BackendEvent, ConsoleEvent, ConsoleHandler, EventFilter, ExecutionContext,
+  NavigationHandler, Popup, PopupHandler, CAPTURE_TIMEOUT, NAVIGATION_TIMEOUT,
+};
+use crate::{
+  cdp::{
+    self,
+    commands::{
+      BrowserBounds, BrowserGetWindowForTarget, BrowserSetWindowBounds, CDPCommand, CDPEvent,
+      FetchContinueRequest, FetchEnable, FetchFailRequest, FetchRequestPattern,
+      PageAddScriptToEvaluateOnNewDocument, PageCaptureScreenshot, PageEnable,
+      PageGetLayoutMetrics, PageGetNavigationHistory, PageNavigate, PageNavigateToHistoryEntry,
+      PagePrintToPdf, PageReload, PageViewport, RuntimeCallArgument, RuntimeCallFunctionOn,
+      RuntimeEvaluate, TargetAttachToTarget, TargetGetTargets, TargetSetAutoAttach,
+      TargetSetDiscoverTargets,
+    },
+    Cdp, ConnectionOptions,
+  },
+  console::{ConsoleLevel, ConsoleMessage, PageException, StackFrame},
+  error::CrowserError,
+  util, ClipRect, PdfOptions, ScreenshotFormat, WindowBounds, WindowState,
+};
+
+/// How long `call_function` waits for the page to have an execution context, unless given a timeout
+const CONTEXT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Backend for Chromium-based browsers, using the Chrome DevTools Protocol
+#[derive(Clone)]
+pub struct CdpBackend {
+  cdp: Cdp,
+  target_id: String,
+  session_id: String,
+  browser_session_id: String,
+
+  // Kept around so they can be installed into out-of-process iframes as well, which get their own sessions
+  preload_scripts: Arc<Mutex<Vec<String>>>,
+
+  // Execution contexts of the page and its iframes, kept up to date from `Runtime` events on every session
+  contexts: Arc<Mutex<Vec<ExecutionContext>>>,
+
+  // Popup handlers of every page on the connection, by target ID. New pages are auto-attached browser-wide, so a single
+  // thread hands each popup to the handler of the page that opened it
+  popup_handlers: Arc<Mutex<HashMap<String, Arc<PopupHandler>>>>,
+
+  // Set for popups, which are paused before they load anything until `resume` is called
+  paused: Arc<AtomicBool>,
+}
+
+impl std::fmt::Debug for CdpBackend {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    // The popup handlers are closures, so only which pages have one is shown
+    let handlers = self.popup_handlers.lock().unwrap();
+
+    f.debug_struct("CdpBackend")
+      .field("cdp", &self.cdp)
+      .field("target_id", &self.target_id)
+      .field("session_id", &self.session_id)
+      .field("browser_session_id", &self.browser_session_id)
+      .field("preload_scripts", &self.preload_scripts)
+      .field("contexts", &self.contexts)
+      .field("popup_handlers", &handlers.keys().collect::<Vec<_>>())
+      .field("paused", &self.paused)
+      .finish()
+  }
+}
+
+impl CdpBackend {
+  pub fn connect(port: u16, options: &ConnectionOptions) -> Result<Self, CrowserError> {
+    Self::with_cdp(cdp::launch(port, options)?)
+  }
+
+  /// Play back a recorded session instead of talking to a browser, to reproduce what happened with it
+  pub fn replay(path: impl AsRef<Path>) -> Result<Self, CrowserError> {
+    Self::with_cdp(Cdp::replay(path)?)
+  }
+
+  fn with_cdp(cdp: Cdp) -> Result<Self, CrowserError> {
+    let mut backend = CdpBackend {
+      cdp,
+      target_id: String::new(),
+      session_id: String::new(),
+      browser_session_id: String::new(),
+      preload_scripts: Arc::new(Mutex::new(vec![])),
+      contexts: Arc::new(Mutex::new(vec![])),
+      popup_handlers: Arc::new(Mutex::new(HashMap::new())),
+      paused: Arc::new(AtomicBool::new(false)),
+    };
+
+    backend.attach()?;
+
+    Ok(backend)
+  }
+
+  fn attach(&mut self) -> Result<(), CrowserError> {
+    let cdp = &self.cdp;
+
+    // Get targets
+    let t_params = TargetGetTargets {};
+    let t_cmd = CDPCommand::new("Target.getTargets", t_params, None);
+    let result = cdp.send(t_cmd, None)?;
+    let result = result.get("result");
+
+    let targets = match result {
+      Some(val) => val,
+      None => {
+        return Err(CrowserError::CDPError(
+          "Attach: No result found".to_string(),
+        ))
+      }
+    };
+
+    let targets = match targets.get("targetInfos") {
+      Some(val) => val,
+      None => return Err(CrowserError::CDPError("No targets found".to_string())),
+    }
+    .as_array();
+
+    let target_id = targets
+      .into_iter()
+      .flatten()
+      .find(|target| target["type"] == "page")
+      .and_then(|target| target["targetId"].as_str())
+      .map(|target_id| target_id.to_string());
+
+    // Set discover targets
+    let cmd = CDPCommand::new(
+      "Target.setDiscoverTargets",
+      TargetSetDiscoverTargets { discover: true },
+      None,
+    );
+    cdp.send(cmd, None)?;
+
+    // Attach to the browser session
+    let cmd = CDPCommand::new(
+      "Target.attachToBrowserTarget",
+      serde_json::Value::Null,
+      None,
+    );
+
+    let b_result = cdp.send(cmd, None)?;
+    let b_result = b_result["result"].get("sessionId");
+
+    if let Some(b_result) = b_result {
+      self.browser_session_id = b_result.as_str().unwrap_or_default().to_string();
+    }
+
+    match target_id {
+      Some(target_id) => self.attach_page(target_id),
+      None => Err(CrowserError::NoTab("Nothing to attach to".to_string())),
+    }
+  }
+
+  /// Attach to a page target and get it ready for use
+  fn attach_page(&mut self, target_id: String) -> Result<(), CrowserError> {
+    let t_params = TargetAttachToTarget {
+      target_id: target_id.clone(),
+      flatten: true,
+    };
+    let t_cmd = CDPCommand::new("Target.attachToTarget", t_params, None);
+    let t_result = self.cdp.send(t_cmd, None)?;
+
+    // The response carries the same session ID as the Target.attachedToTarget event
+    match t_result["result"]["sessionId"].as_str() {
+      Some(session_id) => {
+        self.session_id = session_id.to_string();
+        self.target_id = target_id;
+      }
+      None => {
+        return Err(CrowserError::CDPError(format!(
+          "Failed to attach to target {}",
+          target_id
+        )))
+      }
+    }
+
+    self.setup_page()
+  }
+
+  /// Enable what we need on the page's session, once attached
+  fn setup_page(&self) -> Result<(), CrowserError> {
+    // Page enable
+    let cmd = CDPCommand::new("Page.enable", PageEnable {}, Some(self.session_id.clone()));
+    self.cdp.send(cmd, None)?;
+
+    // Runtime enable, which reports the contexts that already exist, so start listening first
+    self.watch_contexts(self.session_id.clone());
+    let cmd = CDPCommand::new(
+      "Runtime.enable",
+      serde_json::Value::Null,
+      Some(self.session_id.clone()),
+    );
+    self.cdp.send(cmd, None)?;
+
+    // Cross-origin iframes can live in their own process and target, so attach to those too
+    self.watch_frames();
+    self.auto_attach(self.session_id.clone())?;
+
+    Ok(())
+  }
+
+  /// A backend for a page opened by this one, sharing the same connection. The popup was auto-attached paused, so it
+  /// stays that way until `resume` is called.
+  fn attach_popup(&self, target_id: String, session_id: String) -> Result<Self, CrowserError> {
+    let popup = CdpBackend {
+      cdp: self.cdp.clone(),
+      target_id,
+      session_id,
+      browser_session_id: self.browser_session_id.clone(),
+      preload_scripts: Arc::new(Mutex::new(vec![])),
+      contexts: Arc::new(Mutex::new(vec![])),
+      popup_handlers: self.popup_handlers.clone(),
+      paused: Arc::new(AtomicBool::new(true)),
+    };
+
+    popup.setup_page()?;
+
+    Ok(popup)
+  }
+
+  /// Automatically attach to child targets of a session, pausing them until we're ready
+  fn auto_attach(&self, session_id: String) -> Result<(), CrowserError> {
+    self.send(
+      "Target.setAutoAttach",
+      TargetSetAutoAttach {
+        auto_attach: true,
+        wait_for_debugger_on_start: true,
+        flatten: true,
+      },
+      Some(session_id),
+    )?;
+
+    Ok(())
+  }
+
+  /// Where a paused page is going. It hasn't loaded anything yet, but the browser reports the URL as soon as the
+  /// navigation starts, which doesn't need the page to run.
+  fn paused_url(&self, info: &Value) -> String {
+    let target_id = info["targetId"].as_str().unwrap_or_default();
+    let is_known =
+      |url: &Value| matches!(url.as_str(), Some(url) if !url.is_empty() && url != "about:blank");
+
+    if is_known(&info["url"]) {
+      return info["url"].as_str().unwrap_or_default().to_string();
+    }
+
+    // Only subscribed to while waiting, since nothing would read the updates otherwise
+    let changed = self.cdp.subscribe("Target.targetInfoChanged", None);
+
+    // The navigation may have started before we subscribed
+    let mut info = self
+      .send(
+        "Target.getTargetInfo",
+        serde_json::json!({ "targetId": target_id }),
+        None,
+      )
+      .map(|result| result["targetInfo"].clone())
+      .unwrap_or_default();
+    let deadline = Instant::now() + Duration::from_secs(2);
+
+    while !is_known(&info["url"]) {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+
+      match changed.recv_timeout(remaining) {
+        Ok(event) if event.params["targetInfo"]["targetId"] == target_id => {
+          info = event.params["targetInfo"].clone();
+        }
+        Ok(_) => {}
+        Err(_) => return "about:blank".to_string(),
+      }
+    }
+
+    info["url"].as_str().unwrap_or_default().to_string()
+  }
+
+  /// Auto-attach to every new page in the browser, paused before it loads anything, so popups can be allowed or denied
+  /// before any of their scripts run. Popups go to the handler of the page that opened them, everything else is let go.
+  fn dispatch_popups(&self) -> Result<(), CrowserError> {
+    let attached = self
+      .cdp
+      .subscribe("Target.attachedToTarget", self.session(true));
+    let backend = self.clone();
+
+    std::thread::spawn(move || {
+      for event in attached.iter() {
+        let info = &event.params["targetInfo"];
+        let target_id = info["targetId"].as_str().unwrap_or_default().to_string();
+        let session_id = match event.params["sessionId"].as_str() {
+          Some(session_id) => session_id.to_string(),
+          None => continue,
+        };
+
+        let handler = match info["type"] == "page" {
+          true => info["openerId"]
+            .as_str()
+            .and_then(|opener| backend.popup_handlers.lock().unwrap().get(opener).cloned()),
+          false => None,
+        };
+
+        let handler = match handler {
+          Some(handler) => handler,
+          None => {
+            // Not a popup of ours, so it goes back to how it would have been without us
+            backend
+              .send(
+                "Runtime.runIfWaitingForDebugger",
+                serde_json::Value::Null,
+                Some(session_id.clone()),
+              )
+              .unwrap_or_default();
+            backend
+              .send(
+                "Target.detachFromTarget",
+                serde_json::json!({ "sessionId": session_id }),
+                backend.session(true),
+              )
+              .unwrap_or_default();
+            continue;
+          }
+        };
+
+        let url = backend.paused_url(info);
+
+        let popup = match backend.attach_popup(target_id.clone(), session_id) {
+          Ok(popup) => popup,
+          Err(err) => {
+            log::warn!(target: "cdp", "Could not set up popup {}: {}", target_id, err);
+            continue;
+          }
+        };
+
+        handler(Popup {
+          url,
+          backend: Arc::new(popup),
+        });
+      }
+    });
+
+    self.send(
+      "Target.setAutoAttach",
+      TargetSetAutoAttach {
+        auto_attach: true,
+        wait_for_debugger_on_start: true,
+        flatten: true,
+      },
+      self.session(true),
+    )?;
+
+    Ok(())
+  }
+
+  /// Install the preload scripts into every auto-attached iframe before letting it run
+  fn watch_frames(&self) {
+    let rx = self.cdp.subscribe("Target.attachedToTarget", None);
+    let backend = self.clone();
+
+    std::thread::spawn(move || {
+      // Other pages (popups) share the connection, so only handle children of our page and its frames
+      let mut sessions = vec![backend.session_id.clone()];
+
+      for event in rx.iter() {
+        // Events without a session are for targets we attached to ourselves, not auto-attached children
+        match &event.session_id {
+          Some(parent) if sessions.contains(parent) => {}
+          _ => continue,
+        }
+
+        let child = match event.params["sessionId"].as_str() {
+          Some(child) => child.to_string(),
+          None => continue,
+        };
+
+        if event.params["targetInfo"]["type"] == "iframe" {
+          let scripts = backend.preload_scripts.lock().unwrap().clone();
+
+          for script in scripts {
+            backend
+              .send(
+                "Page.addScriptToEvaluateOnNewDocument",
+                PageAddScriptToEvaluateOnNewDocument { source: script },
+                Some(child.clone()),
+              )
+              .unwrap_or_default();
+          }
+
+          // Frames nested in this one get attached through this one
+          backend.auto_attach(child.clone()).unwrap_or_default();
+          sessions.push(child.clone());
+
+          backend.watch_contexts(child.clone());
+          backend
+            .send(
+              "Runtime.enable",
+              serde_json::Value::Null,
+              Some(child.clone()),
+            )
+            .unwrap_or_default();
+        }
+
+        // Everything auto-attached is paused until we say otherwise
+        backend
+          .send(
+            "Runtime.runIfWaitingForDebugger",
+            serde_json::Value::Null,
+            Some(child),
+          )
+          .unwrap_or_default();
+      }
+    });
+  }
+
+  /// Keep track of the execution contexts on a session, once `Runtime` is enabled on it.
+  /// Contexts on other sessions are left alone, except those of frames detached from this one.
+  fn watch_contexts(&self, session_id: String) {
+    // A single subscription keeps the events in order, so a context is never destroyed before it's created
+    let rx = self.cdp.subscribe_many(
+      &[
+        "Runtime.executionContextCreated",
+        "Runtime.executionContextDestroyed",
+        "Runtime.executionContextsCleared",
+        "Target.detachedFromTarget",
+      ],
+      Some(session_id.clone()),
+    );
+    let contexts = self.contexts.clone();
+    let target_id = self.target_id.clone();
+    let top_session = self.session_id.clone();
+
+    std::thread::spawn(move || {
+      // IDs are only unique within a session, so the session is part of ours
+      let prefix = format!("{}:", session_id);
+
+      for event in rx.iter() {
+        let params = &event.params;
+        let mut contexts = contexts.lock().unwrap();
+
+        match event.method.as_str() {
+          // Isolated worlds (extensions, the browser's own scripts) can't reach the page's IPC anyway
+          "Runtime.executionContextCreated"
+            if params["context"]["auxData"]["isDefault"] == true =>
+          {
+            let context = &params["context"];
+
+            contexts.push(ExecutionContext {
+              id: format!("{}{}", prefix, context["id"]),
+              origin: context["origin"].as_str().unwrap_or_default().to_string(),
+              top_level: session_id == top_session
+                && context["auxData"]["frameId"] == target_id.as_str(),
+            });
+          }
+          "Runtime.executionContextDestroyed" => {
+            let id = format!("{}{}", prefix, params["executionContextId"]);
+            contexts.retain(|context| context.id != id);
+          }
+          "Runtime.executionContextsCleared" => {
+            contexts.retain(|context| !context.id.starts_with(&prefix));
+          }
+          // Out-of-process iframes going away take their sessions with them, without destroying their contexts first
+          "Target.detachedFromTarget" => {
+            let child = format!("{}:", params["sessionId"].as_str().unwrap_or_default());
+            contexts.retain(|context| !context.id.starts_with(&child));
+          }
+          _ => {}
+        }
+      }
+    });
+  }
+
+  /// Evaluate a script on a session, in a specific context or the session's own
+  fn evaluate_on(
+    &self,
+    session_id: Option<String>,
+    context_id: Option<i64>,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    let result = self.send_with_timeout(
+      "Runtime.evaluate",
+      RuntimeEvaluate {
+        expression: script.to_string(),
+        context_id,
+        await_promise: Some(true),
+        return_by_value: Some(true),
+      },
+      session_id,
+      timeout,
+    )?;
+
+    if let Some(details) = result.get("exceptionDetails") {
+      return Err(CrowserError::ScriptError(page_exception(details)));
+    }
+
+    Ok(remote_object_value(&result["result"]))
+  }
+
+  /// The ID of the page's own execution context. Right after a navigation there isn't one yet, so this waits for it.
+  fn page_context(&self, timeout: Option<Duration>) -> Result<i64, CrowserError> {
+    let start = Instant::now();
+    let prefix = format!("{}:", self.session_id);
+
+    loop {
+      let id = self
+        .contexts
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|context| context.top_level)
+        .and_then(|context| context.id.strip_prefix(&prefix)?.parse().ok());
+
+      match id {
+        Some(id) => return Ok(id),
+        None if start.elapsed() >= timeout.unwrap_or(CONTEXT_TIMEOUT) => {
+          return Err(CrowserError::Timeout(
+            "Waiting for the page's execution context".to_string(),
+          ))
+        }
+        None => std::thread::sleep(Duration::from_millis(10)),
+      }
+    }
+  }
+
+  /// Send a command on this backend's connection, turning protocol errors into `Err`
+  fn send(
+    &self,
+    method: impl AsRef<str>,
+    params: impl serde::Serialize,
+    session_id: Option<String>,
+  ) -> Result<Value, CrowserError> {
+    self.send_with_timeout(method, params, session_id, None)
+  }
+
+  /// `send`, for commands that can take longer than the connection's default timeout
+  fn send_with_timeout(
+    &self,
+    method: impl AsRef<str>,
+    params: impl serde::Serialize,
+    session_id: Option<String>,
+    timeout: Option<Duration>,
+  ) -> Result<Value, CrowserError> {
+    let cmd = CDPCommand::new(method, params, session_id);
+    let result = self.cdp.send(cmd, timeout)?;
+
+    if let Some(err) = result.get("error") {
+      return Err(CrowserError::CDPError(format!(
+        "{} ({})",
+        err["message"].as_str().unwrap_or("Unknown error"),
+        err["code"]
+      )));
+    }
+
+    Ok(result["result"].clone())
+  }
+
+  /// The session ID used for page-level commands, or for browser-level commands if `browser` is true.
+  /// `None` means the root connection, which is used when the browser doesn't support attaching to the browser target.
+  pub fn session(&self, browser: bool) -> Option<String> {
+    let session_id = match browser {
+      true => &self.browser_session_id,
+      false => &self.session_id,
+    };
+
+    match session_id.is_empty() {
+      true => None,
+      false => Some(session_id.clone()),
+    }
+  }
+
+  /// Send an arbitrary CDP command and return its `result`, or an error if the browser rejected it.
+  pub fn send_raw(
+    &self,
+    method: impl AsRef<str>,
+    params: Value,
+    browser: bool,
+  ) -> Result<Value, CrowserError> {
+    self.send(method, params, self.session(browser))
+  }
+
+  /// Subscribe to raw CDP events. See `Cdp::subscribe`.
+  pub fn subscribe_raw(
+    &self,
+    method: impl AsRef<str>,
+    session_id: Option<String>,
+  ) -> flume::Receiver<CDPEvent> {
+    self.cdp.subscribe(method, session_id)
+  }
+}
+
+impl Backend for CdpBackend {
+  fn evaluate(
+    &self,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    self.evaluate_on(self.session(false), None, script, timeout)
+  }
+
+  fn evaluate_in(
+    &self,
+    context: &ExecutionContext,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    let (session_id, context_id) = match context.id.rsplit_once(':') {
+      Some((session_id, context_id)) => (session_id, context_id.parse().ok()),
+      None => ("", None),
+    };
+
+    if context_id.is_none() {
+      return Err(CrowserError::CDPError(format!(
+        "Unknown execution context: {}",
+        context.id
+      )));
+    }
+
+    self.evaluate_on(
+      Some(session_id.to_string()).filter(|s| !s.is_empty()),
+      context_id,
+      script,
+      timeout,
+    )
+  }
+
+  fn execution_contexts(&self) -> Vec<ExecutionContext> {
+    self.contexts.lock().unwrap().clone()
+  }
+
+  fn call_function(
+    &self,
+    function: &str,
+    args: Vec<Value>,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError> {
+    let result = self.send_with_timeout(
+      "Runtime.callFunctionOn",
+      RuntimeCallFunctionOn {
+        function_declaration: function.to_string(),
+        execution_context_id: self.page_context(timeout)?,
+        arguments: args
+          .into_iter()
+          .map(|value| RuntimeCallArgument { value })
+          .collect(),
+        await_promise: Some(true),
+        return_by_value: Some(true),
+      },
+      self.session(false),
+      timeout,
+    )?;
+
+    if let Some(details) = result.get("exceptionDetails") {
+      return Err(CrowserError::ScriptError(page_exception(details)));
+    }
+
+    Ok(remote_object_value(&result["result"]))
+  }
+
+  fn add_preload_script(&self, script: &str) -> Result<String, CrowserError> {
+    self
+      .preload_scripts
+      .lock()
+      .unwrap()
+      .push(script.to_string());
+
+    // Runs before any of the page's own scripts, on every navigation and in every same-process frame
+    let result = self.send(
+      "Page.addScriptToEvaluateOnNewDocument",
+      PageAddScriptToEvaluateOnNewDocument {
+        source: script.to_string(),
+      },
+      self.session(false),
+    )?;
+
+    Ok(
+      result["identifier"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string(),
+    )
+  }
+
+  fn navigate(&self, url: &str) -> Result<(), CrowserError> {
+    // This responds once the server has, which can easily take longer than the default timeout
+    let result = self.send_with_timeout(
+      "Page.navigate",
+      PageNavigate {
+        url: url.to_string(),
+      },
+      self.session(false),
+      Some(NAVIGATION_TIMEOUT),
+    )?;
+
+    // Failing to load the page (DNS errors and such) isn't a protocol error, so it has to be checked separately
+    if let Some(error) = result["errorText"].as_str() {
+      return Err(CrowserError::CDPError(format!(
+        "Failed to navigate to {}: {}",
+        url, error
+      )));
+    }
+
+    Ok(())
+  }
+
+  fn reload(&self, ignore_cache: bool) -> Result<(), CrowserError> {
+    self.send(
+      "Page.reload",
+      PageReload {
+        ignore_cache: Some(ignore_cache),
+        script_to_evaluate_on_load: None,
+      },
+      self.session(false),
+    )?;
+
+    Ok(())
+  }
+
+  fn traverse_history(&self, delta: i64) -> Result<(), CrowserError> {
+    let history = self.send(
+      "Page.getNavigationHistory",
+      PageGetNavigationHistory {},
+      self.session(false),
+    )?;
+    let index = history["currentIndex"].as_i64().unwrap_or_default() + delta;
+
+    let entry_id = match history["entries"].get(index.max(0) as usize) {
+      Some(entry) if index >= 0 => entry["id"].as_i64().unwrap_or_default(),
+      _ => {
+        return Err(CrowserError::CDPError(format!(
+          "No history entry {} away from the current one",
+          delta
+        )))
+      }
+    };
+
+    self.send(
+      "Page.navigateToHistoryEntry",
+      PageNavigateToHistoryEntry { entry_id },
+      self.session(false),
+    )?;
+
+    Ok(())
+  }
+
+  fn current_url(&self) -> Result<String, CrowserError> {
+    let history = self.send(
+      "Page.getNavigationHistory",
+      PageGetNavigationHistory {},
+      self.session(false),
+    )?;
+    let index = history["currentIndex"].as_u64().unwrap_or_default() as usize;
+
+    Ok(
+      history["entries"][index]["url"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string(),
+    )
+  }
+
+  fn subscribe(&self, event: BackendEvent) -> Result<flume::Receiver<Value>, CrowserError> {
+    let target_id = self.target_id.clone();
+    let (method, session_id, filter): (&str, Option<String>, EventFilter) = match event {
+      BackendEvent::Closed => (
+        "Target.targetDestroyed",
+        None,
+        Box::new(move |params| params["targetId"] == target_id.as_str()),
+      ),
+    };
+
+    let rx = self.cdp.subscribe(method, session_id);
+    // A receiver that falls behind backs up into the connection's queue for `rx`, which then drops events as usual
+    let (tx, params_rx) = flume::bounded(1);
+
+    // Forward only the events that are actually about our page. Ends when either side goes away
+    std::thread::spawn(move || {
+      for evt in rx.iter() {
+        if filter(&evt.params) && tx.send(evt.params).is_err() {
+          break;
+        }
+      }
+    });
+
+    Ok(params_rx)
+  }
+
+  fn intercept_navigation(&self, handler: NavigationHandler) -> Result<(), CrowserError> {
+    let rx = self
+      .cdp
+      .subscribe("Fetch.requestPaused", self.session(false));
+
+    // Only document requests are paused, so subresources aren't slowed down
+    self.send(
+      "Fetch.enable",
+      FetchEnable {
+        patterns: vec![FetchRequestPattern {
+          url_pattern: Some("*".to_string()),
+          resource_type: Some("Document".to_string()),
+          request_stage: None,
+        }],
+      },
+      self.session(false),
+    )?;
+
+    let backend = self.clone();
+
+    std::thread::spawn(move || {
+      for event in rx.iter() {
+        let request_id = event.params["requestId"]
+          .as_str()
+          .unwrap_or_default()
+          .to_string();
+        let url = event.params["request"]["url"].as_str().unwrap_or_default();
+
+        // The main frame's ID is the same as its target's. Anything else is an iframe, which we leave alone
+        let allowed = event.params["frameId"] != backend.target_id.as_str() || handler(url);
+
+        let result = match allowed {
+          true => backend.send(
+            "Fetch.continueRequest",
+            FetchContinueRequest { request_id },
+            backend.session(false),
+          ),
+          // Aborted navigations don't show an error page, the window just stays where it is
+          false => backend.send(
+            "Fetch.failRequest",
+            FetchFailRequest {
+              request_id,
+              error_reason: "Aborted".to_string(),
+            },
+            backend.session(false),
+          ),
+        };
+
+        result.unwrap_or_default();
+      }
+    });
+
+    Ok(())
+  }
+
+  fn watch_popups(&self, handler: PopupHandler) -> Result<(), CrowserError> {
+    let first = {
+      let mut handlers = self.popup_handlers.lock().unwrap();
+      let first = handlers.is_empty();
+      handlers.insert(self.target_id.clone(), Arc::new(handler));
+      first
+    };
+
+    if first {
+      self.dispatch_popups()?;
+    }
+
+    Ok(())
+  }
+
+  fn resume(&self) -> Result<bool, CrowserError> {
+    if !self.paused.swap(false, Ordering::Relaxed) {
+      return Ok(false);
+    }
+
+    self.send(
+      "Runtime.runIfWaitingForDebugger",
+      serde_json::Value::Null,
+      self.session(false),
+    )?;
+
+    Ok(true)
+  }
+
+  fn watch_console(&self, handler: ConsoleHandler) -> Result<(), CrowserError> {
+    // Same-process iframes report through the page's session as well
+    let messages = self
+      .cdp
+      .subscribe("Runtime.consoleAPICalled", self.session(false));
+    let t_handler = handler.clone();
+
+    std::thread::spawn(move || {
+      for event in messages.iter() {
+        t_handler(ConsoleEvent::Message(console_message(&event.params)));
+      }
+    });
+
+    let exceptions = self
+      .cdp
+      .subscribe("Runtime.exceptionThrown", self.session(false));
+
+    std::thread::spawn(move || {
+      for event in exceptions.iter() {
+        handler(ConsoleEvent::Exception(page_exception(
+          &event.params["exceptionDetails"],
+        )));
+      }
+    });
+
+    Ok(())
+  }
+
+  fn screenshot(
+    &self,
+    format: ScreenshotFormat,
+    clip: Option<ClipRect>,
+    full_page: bool,
+  ) -> Result<Vec<u8>, CrowserError> {
+    let clip = match (clip, full_page) {
+      (Some(clip), _) => Some(PageViewport {
+        x: clip.x,
+        y: clip.y,
+        width: clip.width,
+        height: clip.height,
+        scale: 1.0,
+      }),
+      // The whole document is only captured if we ask for all of it explicitly
+      (None, true) => {
+        let metrics = self.send(
+          "Page.getLayoutMetrics",
+          PageGetLayoutMetrics {},
+          self.session(false),
+        )?;
+        let size = &metrics["cssContentSize"];
+
+        Some(PageViewport {
+          x: 0.0,
+          y: 0.0,
+          width: size["width"].as_f64().unwrap_or_default(),
+          height: size["height"].as_f64().unwrap_or_default(),
+          scale: 1.0,
+        })
+      }
+      (None, false) => None,
+    };
+
+    let result = self.send_with_timeout(
+      "Page.captureScreenshot",
+      PageCaptureScreenshot {
+        format,
+        capture_beyond_viewport: clip.is_some(),
+        clip,
+      },
+      self.session(false),
+      Some(CAPTURE_TIMEOUT),
+    )?;
+
+    util::base64::decode(result["data"].as_str().unwrap_or_default())
+      .ok_or_else(|| CrowserError::CDPError("Invalid screenshot data".to_string()))
+  }
+
+  fn print_to_pdf(&self, options: PdfOptions) -> Result<Vec<u8>, CrowserError> {
+    let result = self.send_with_timeout(
+      "Page.printToPDF",
+      PagePrintToPdf {
+        landscape: options.landscape,
+        display_header_footer: options.header_template.is_some()
+          || options.footer_template.is_some(),
+        print_background: options.print_background,
+        paper_width: options.paper_width,
+        paper_height: options.paper_height,
+        margin_top: options.margin_top,
+        margin_bottom: options.margin_bottom,
+        margin_left: options.margin_left,
+        margin_right: options.margin_right,
+        // Chromium fills in a default for whichever one is missing, so an empty one is needed to actually leave it out
+        header_template: options
+          .header_template
+          .clone()
+          .or(options.footer_template.as_ref().map(|_| String::new())),
+        footer_template: options
+          .footer_template
+          .clone()
+          .or(options.header_template.as_ref().map(|_| String::new())),
+      },
+      self.session(false),
+      Some(CAPTURE_TIMEOUT),
+    )?;
+
+    util::base64::decode(result["data"].as_str().unwrap_or_default())
+      .ok_or_else(|| CrowserError::CDPError("Invalid PDF data".to_string()))
+  }
+
+  fn window_bounds(&self) -> Result<WindowBounds, CrowserError> {
+    let result = self.send(
+      "Browser.getWindowForTarget",
+      BrowserGetWindowForTarget {
+        target_id: Some(self.target_id.clone()),
+      },
+      None,
+    )?;
+    let bounds = &result["bounds"];
+
+    Ok(WindowBounds {
+      x: bounds["left"].as_i64().unwrap_or_default() as i32,
+      y: bounds["top"].as_i64().unwrap_or_default() as i32,
+      width: bounds["width"].as_u64().unwrap_or_default() as u32,
+      height: bounds["height"].as_u64().unwrap_or_default() as u32,
+      state: serde_json::from_value(bounds["windowState"].clone()).unwrap_or_default(),
+    })
+  }
+
+  fn set_window_bounds(&self, bounds: WindowBounds) -> Result<(), CrowserError> {
+    let result = self.send(
+      "Browser.getWindowForTarget",
+      BrowserGetWindowForTarget {
+        target_id: Some(self.target_id.clone()),
+      },
+      None,
+    )?;
+    let window_id = result["windowId"].as_i64().unwrap_or_default();
+    let current: WindowState =
+      serde_json::from_value(result["bounds"]["windowState"].clone()).unwrap_or_default();
+
+    // The window state can't be changed at the same time as anything else, and position/size only apply to normal windows
+    if bounds.state != WindowState::Normal || current != WindowState::Normal {
+      self.send(
+        "Browser.setWindowBounds",
+        BrowserSetWindowBounds {
+          window_id,
+          bounds: BrowserBounds {
+            window_state: Some(bounds.state),
+            ..Default::default()
+          },
+        },
+        None,
+      )?;
+    }
+
+    if bounds.state == WindowState::Normal {
+      self.send(
+        "Browser.setWindowBounds",
+        BrowserSetWindowBounds {
+          window_id,
+          bounds: BrowserBounds {
+            left: Some(bounds.x),
+            top: Some(bounds.y),
+            width: Some(bounds.width),
+            height: Some(bounds.height),
+            window_state: None,
+          },
+        },
+        None,
+      )?;
+    }
+
+    Ok(())
+  }
+
+  fn set_viewport(&self, width: u32, height: u32) -> Result<(), CrowserError> {
+    self.send(
+      "Emulation.setDeviceMetricsOverride",
+      serde_json::json!({
+        "width": width,
+        "height": height,
+        "deviceScaleFactor": 0,
+        "mobile": false,
+      }),
+      self.session(false),
+    )?;
+
+    Ok(())
+  }
+
+  fn close(&self) -> Result<(), CrowserError> {
+    self.send(
+      "Target.closeTarget",
+      serde_json::json!({ "targetId": self.target_id }),
+      None,
+    )?;
+
+    Ok(())
+  }
+
+  fn cdp(&self) -> Option<&CdpBackend> {
+    Some(self)
+  }
+}
+
+/// Convert a `Runtime.consoleAPICalled` event
+fn console_message(params: &Value) -> ConsoleMessage {
+  let level = match params["type"].as_str().unwrap_or_default() {
+    "debug" => ConsoleLevel::Debug,
+    "info" => ConsoleLevel::Info,
+    "warning" => ConsoleLevel::Warn,
+    "error" | "assert" => ConsoleLevel::Error,
+    _ => ConsoleLevel::Log,
+  };
+  let args = params["args"].as_array().cloned().unwrap_or_default();
+
+  ConsoleMessage {
+    level,
+    text: args
+      .iter()
+      .map(remote_object_to_string)
+      .collect::<Vec<_>>()
+      .join(" "),
+    args: args.iter().map(remote_object_to_json).collect(),
+    stack: StackFrame::from_stack_trace(&params["stackTrace"]),
+  }
+}
+
+/// Convert the `ExceptionDetails` of an uncaught exception
+fn page_exception(details: &Value) -> PageException {
+  let exception = &details["exception"];
+
+  // Errors describe themselves as their message followed by their stack, and anything else thrown is just a value
+  let message = match exception["description"].as_str() {
+    Some(description) => description.lines().next().unwrap_or_default().to_string(),
+    None if exception.is_object() => remote_object_to_string(exception),
+    None => details["text"].as_str().unwrap_or_default().to_string(),
+  };
+
+  // The details have the same location fields as a stack frame
+  let location = StackFrame::from_protocol(details);
+
+  PageException {
+    message,
+    url: location.url,
+    line: location.line,
+    column: location.column,
+    stack: StackFrame::from_stack_trace(&details["stackTrace"]),
+  }
+}
+
+/// The value of a `RemoteObject` returned by value, with `undefined` as `None`.
+/// Numbers JSON can't represent become `null` like they would with `JSON.stringify`, and bigints become strings.
+fn remote_object_value(obj: &Value) -> Option<Value> {
+  if obj["type"] == "undefined" {
+    return None;
+  }
+
+  if let Some(value) = obj.get("value") {
+    return Some(value.clone());
+  }
+
+  let value = match obj["unserializableValue"].as_str() {
+    Some("-0") => Value::from(0),
+    Some(bigint) if bigint.ends_with('n') => Value::from(bigint.trim_end_matches('n')),
+    _ => Value::Null,
+  };
+
+  Some(value)
+}
+
+/// Convert a CDP `RemoteObject` into plain JSON, as far as the browser described it.
+/// Objects are only described by a shallow preview, and anything without a JSON equivalent becomes its description.
+fn remote_object_to_json(obj: &Value) -> Value {
+  if let Some(value) = obj.get("value") {
+    return value.clone();
+  }
+
+  if let Some(preview) = obj.get("preview") {
+    return preview_to_json(preview);
+  }
+
+  match obj["type"].as_str() {
+    Some("undefined") => Value::Null,
+    _ => obj
+      .get("unserializableValue")
+      .or_else(|| obj.get("description"))
+      .cloned()
+      .unwrap_or_default(),
+  }
+}
+
+fn preview_to_json(preview: &Value) -> Value {
+  let properties = preview["properties"].as_array().into_iter().flatten();
+  let value_of = |property: &Value| match property.get("valuePreview") {
+    Some(preview) => preview_to_json(preview),
+    None => {
+      let value = property["value"].as_str().unwrap_or_default();
+
+      match property["type"].as_str() {
+        Some("string") => Value::from(value),
+        Some("number" | "boolean") => serde_json::from_str(value).unwrap_or_default(),
+        Some("object") if property["subtype"] == "null" => Value::Null,
+        Some("undefined") => Value::Null,
+        _ => Value::from(value),
+      }
+    }
+  };
+
+  if preview["subtype"] == "array" {
+    Value::Array(properties.map(value_of).collect())
+  } else {
+    Value::Object(
+      properties
+        .map(|property| {
+          let name = property["name"].as_str().unwrap_or_default().to_string();
+          (name, value_of(property))
+        })
+        .collect(),
+    )
+  }
+}
+
+/// How a console argument reads in the browser's devtools, more or less
+fn remote_object_to_string(obj: &Value) -> String {
+  match (obj["type"].as_str(), obj.get("value")) {
+    (Some("string"), Some(Value::String(text))) => text.clone(),
+    (Some("undefined"), _) => "undefined".to_string(),
+    (_, Some(value)) => value.to_string(),
+    _ if obj.get("preview").is_some() => remote_object_to_json(obj).to_string(),
+    _ => remote_object_to_json(obj)
+      .as_str()
+      .unwrap_or_default()
+      .to_string(),
+  }
+}
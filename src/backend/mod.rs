@@ -0,0 +1,200 @@
+/*!
+# Protocol backends
+
+Everything `BrowserIpc` and `Window` need from the browser goes through the `Backend` trait, so the IPC and window code
+doesn't have to care whether it is talking CDP, WebDriver BiDi, or something else entirely.
+*/
+
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use serde_json::Value;
+
+use crate::{
+  bidi::Bidi,
+  browser::BrowserKind,
+  cdp::ConnectionOptions,
+  console::{ConsoleMessage, PageException},
+  error::CrowserError,
+  ClipRect, PdfOptions, ScreenshotFormat, WindowBounds,
+};
+
+pub mod bidi;
+pub mod cdp;
+
+use self::cdp::CdpBackend;
+
+/// How long to wait for a navigation to get a response from the server, which may also be held up by a navigation policy
+pub(crate) const NAVIGATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for screenshots and PDFs, which can take a while for long documents
+pub(crate) const CAPTURE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Protocol-agnostic events a backend can report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendEvent {
+  /// The window's page was closed
+  Closed,
+}
+
+/// Decides whether a protocol event's params are relevant to the window, when a backend subscribes on our behalf
+pub type EventFilter = Box<dyn Fn(&Value) -> bool + Send>;
+
+/// Decides whether the window's page may navigate to a URL
+pub type NavigationHandler = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// A page opened by the window's page, with `window.open` or a `target="_blank"` link for example
+pub struct Popup {
+  /// Where the popup is going. `about:blank` if it didn't navigate anywhere in time
+  pub url: String,
+  /// Already attached to the popup, which is otherwise left alone until the handler decides what to do with it.
+  /// Popups that haven't loaded anything yet stay that way until they're `resume`d.
+  pub backend: Arc<dyn Backend>,
+}
+
+pub type PopupHandler = Box<dyn Fn(Popup) + Send + Sync>;
+
+/// Console output and uncaught exceptions from the window's page
+pub enum ConsoleEvent {
+  Message(ConsoleMessage),
+  Exception(PageException),
+}
+
+pub type ConsoleHandler = Arc<dyn Fn(ConsoleEvent) + Send + Sync>;
+
+/// Where a frame's own scripts run, in the window's page or one of its iframes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionContext {
+  /// Only meaningful to the backend that reported the context
+  pub id: String,
+  /// The origin of the frame's document, like `https://example.com`. Opaque origins (sandboxed iframes, `data:` URLs) are `null`
+  pub origin: String,
+  /// Whether the context belongs to the page itself, rather than an iframe
+  pub top_level: bool,
+}
+
+pub trait Backend: Debug + Send + Sync {
+  /// Evaluate a script in the window's page, awaiting it if it returns a promise, and return the result as JSON.
+  /// `undefined` is `None`, and exceptions (including rejected promises) are `CrowserError::ScriptError`.
+  /// Gives up with `CrowserError::Timeout` after `timeout`, or the connection's default for commands if it's `None`.
+  fn evaluate(
+    &self,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError>;
+
+  /// Call a JavaScript function (given as its source, like `(a, b) => a + b`) in the window's page with JSON arguments.
+  /// The result and timeout are handled like `evaluate`'s.
+  fn call_function(
+    &self,
+    function: &str,
+    args: Vec<Value>,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError>;
+
+  /// Like `evaluate`, but in a specific execution context instead of the page's own.
+  fn evaluate_in(
+    &self,
+    context: &ExecutionContext,
+    script: &str,
+    timeout: Option<Duration>,
+  ) -> Result<Option<Value>, CrowserError>;
+
+  /// The execution contexts of the page and its iframes that currently exist, as reported by the browser.
+  /// Only the contexts the frames' own scripts run in are included, not ones belonging to extensions or the browser itself.
+  fn execution_contexts(&self) -> Vec<ExecutionContext>;
+
+  /// Register a script to run in every new document. Returns an identifier for the script.
+  fn add_preload_script(&self, script: &str) -> Result<String, CrowserError>;
+
+  /// Navigate the window's page to a new URL. Returns once the navigation has started, or at the latest once the server
+  /// has responded, never waiting for the page to load.
+  fn navigate(&self, url: &str) -> Result<(), CrowserError>;
+
+  /// Reload the window's page, optionally bypassing the cache.
+  fn reload(&self, ignore_cache: bool) -> Result<(), CrowserError>;
+
+  /// Move through the page's session history, e.g. -1 to go back one page. Errors if there is no such entry.
+  fn traverse_history(&self, delta: i64) -> Result<(), CrowserError>;
+
+  /// The URL of the page currently shown in the window.
+  fn current_url(&self) -> Result<String, CrowserError>;
+
+  /// Subscribe to an event. The receiver gets the protocol-specific event parameters, and dropping it unsubscribes.
+  fn subscribe(&self, event: BackendEvent) -> Result<flume::Receiver<Value>, CrowserError>;
+
+  /// Pause every top-level navigation of the window's page until `handler` decides whether it may continue.
+  /// Denied navigations leave the current page as it is. Navigations inside iframes are not affected.
+  fn intercept_navigation(&self, handler: NavigationHandler) -> Result<(), CrowserError>;
+
+  /// Call `handler` for every popup the window's page opens.
+  fn watch_popups(&self, handler: PopupHandler) -> Result<(), CrowserError>;
+
+  /// Let a popup that was handed over before loading anything start loading. Returns `false` if the page wasn't paused,
+  /// in which case it has already loaded whatever it was opened for.
+  fn resume(&self) -> Result<bool, CrowserError> {
+    Ok(false)
+  }
+
+  /// Call `handler` for everything the window's page logs to the console, and every exception it doesn't catch.
+  fn watch_console(&self, handler: ConsoleHandler) -> Result<(), CrowserError>;
+
+  /// Capture the page as an image. `clip` is in document coordinates, and `full_page` captures the whole document
+  /// instead of just the visible part, when there is no `clip`.
+  fn screenshot(
+    &self,
+    format: ScreenshotFormat,
+    clip: Option<ClipRect>,
+    full_page: bool,
+  ) -> Result<Vec<u8>, CrowserError>;
+
+  /// Render the page to a PDF.
+  fn print_to_pdf(&self, options: PdfOptions) -> Result<Vec<u8>, CrowserError>;
+
+  /// Get the position, size and state of the browser window containing the page.
+  fn window_bounds(&self) -> Result<WindowBounds, CrowserError>;
+
+  /// Move, resize, or change the state of the browser window. Position and size are ignored unless `state` is `Normal`.
+  fn set_window_bounds(&self, bounds: WindowBounds) -> Result<(), CrowserError>;
+
+  /// Size the page's viewport, for headless windows the command line couldn't size.
+  fn set_viewport(&self, width: u32, height: u32) -> Result<(), CrowserError>;
+
+  /// Close the window's page.
+  fn close(&self) -> Result<(), CrowserError>;
+
+  /// Access to the underlying CDP connection, for raw passthrough. `None` for backends that don't speak CDP.
+  fn cdp(&self) -> Option<&CdpBackend> {
+    None
+  }
+}
+
+/// Connect to a freshly launched browser using whatever protocol suits it best.
+/// Only CDP sessions can be recorded, so `options.recording` is ignored for Gecko-based browsers.
+pub fn connect(
+  kind: BrowserKind,
+  port: u16,
+  options: &ConnectionOptions,
+) -> Result<Arc<dyn Backend>, CrowserError> {
+  match kind {
+    BrowserKind::Gecko => {
+      if let Some(path) = &options.recording {
+        log::warn!(target: "browser", "Not recording to {}, since Gecko doesn't speak CDP", path.display());
+      }
+
+      Ok(Arc::new(Bidi::connect(port, options.tracing)?))
+    }
+    _ => Ok(Arc::new(CdpBackend::connect(port, options)?)),
+  }
+}
+
+/// New pages start out on `about:blank` before navigating to where they were opened for, so give them a moment to get going
+pub fn wait_for_url(backend: &dyn Backend) -> String {
+  for _ in 0..40 {
+    match backend.current_url() {
+      Ok(url) if !url.is_empty() && url != "about:blank" => return url,
+      _ => std::thread::sleep(std::time::Duration::from_millis(50)),
+    }
+  }
+
+  "about:blank".to_string()
+}
@@ -0,0 +1,351 @@
+/*!
+# WebDriver BiDi
+
+Gecko-based browsers have deprecated (and in newer releases, removed) their Chrome DevTools Protocol support in favor of
+[WebDriver BiDi](https://w3c.github.io/webdriver-bidi/). This is the BiDi equivalent of the `cdp` module, again only implementing
+what Crowser actually needs.
+
+The BiDi wire format is close enough to CDP's (`id`/`method`/`params` commands, `method`/`params` events) that the same websocket
+connection handling is used for both, only the messages themselves differ.
+*/
+
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+  backend::ExecutionContext,
+  cdp::{
+    commands::{CDPCommand, CDPEvent},
+    Cdp,
+  },
+  error::CrowserError,
+};
+
+/// A WebDriver BiDi session attached to the window's top-level browsing context
+#[derive(Debug, Clone)]
+pub struct Bidi {
+  conn: Cdp,
+  context: String,
+  client_window: String,
+  /// The realms of the browsing context and its iframes, with realm IDs as context IDs
+  realms: Arc<Mutex<Vec<ExecutionContext>>>,
+}
+
+impl Bidi {
+  /// Connect to the browser's BiDi endpoint, start a session, and find the window's browsing context.
+  /// With `tracing`, every frame is logged like it would be for CDP.
+  pub fn connect(port: u16, tracing: bool) -> Result<Self, CrowserError> {
+    let url = format!("ws://127.0.0.1:{}/session", port);
+    let mut conn = Cdp::new();
+    conn.set_tracing(tracing);
+    let mut connected = false;
+
+    // 1 minute wait, the browser might take a bit to open the port
+    for _ in 0..600 {
+      match conn.connect_url(&url) {
+        Ok(_) => {
+          connected = true;
+          break;
+        }
+        Err(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
+      }
+    }
+
+    if !connected {
+      return Err(CrowserError::BidiError(
+        "No browser instance to connect to".to_string(),
+      ));
+    }
+
+    let mut bidi = Bidi {
+      conn,
+      context: String::new(),
+      client_window: String::new(),
+      realms: Arc::new(Mutex::new(vec![])),
+    };
+
+    bidi.send(
+      "session.new",
+      serde_json::json!({ "capabilities": {} }),
+      None,
+    )?;
+
+    let tree = bidi.send(
+      "browsingContext.getTree",
+      serde_json::json!({ "maxDepth": 0 }),
+      None,
+    )?;
+
+    // Older browsers don't report the client window, in which case the first window is assumed to be ours
+    bidi.client_window = tree["contexts"][0]["clientWindow"]
+      .as_str()
+      .unwrap_or_default()
+      .to_string();
+
+    bidi.context = match tree["contexts"][0]["context"].as_str() {
+      Some(context) => context.to_string(),
+      None => {
+        return Err(CrowserError::BidiError(
+          "No browsing contexts found".to_string(),
+        ))
+      }
+    };
+
+    bidi.track_realms()?;
+
+    Ok(bidi)
+  }
+
+  /// Another top-level browsing context on the same session, such as a popup
+  pub fn for_context(&self, context: impl AsRef<str>, client_window: impl AsRef<str>) -> Self {
+    let bidi = Bidi {
+      conn: self.conn.clone(),
+      context: context.as_ref().to_string(),
+      client_window: client_window.as_ref().to_string(),
+      realms: Arc::new(Mutex::new(vec![])),
+    };
+
+    bidi.track_realms().unwrap_or_default();
+
+    bidi
+  }
+
+  /// The ID of the top-level browsing context this session controls
+  pub fn context(&self) -> &str {
+    &self.context
+  }
+
+  /// The ID of the OS window containing the browsing context
+  pub fn client_window(&self) -> &str {
+    &self.client_window
+  }
+
+  /// The window realms of the browsing context and the iframes in it
+  pub fn realms(&self) -> Vec<ExecutionContext> {
+    self.realms.lock().unwrap().clone()
+  }
+
+  /// Keep `realms` up to date. Realms only say which browsing context they're in, so the iframes in ours are tracked too.
+  fn track_realms(&self) -> Result<(), CrowserError> {
+    // A single subscription keeps the events in order, so an iframe is always known before its realm
+    let events = [
+      "browsingContext.contextCreated",
+      "browsingContext.contextDestroyed",
+      "script.realmCreated",
+      "script.realmDestroyed",
+    ];
+    let rx = self.conn.subscribe_many(&events, None);
+
+    self.send(
+      "session.subscribe",
+      serde_json::json!({ "events": events }),
+      None,
+    )?;
+
+    let tree = self.send(
+      "browsingContext.getTree",
+      serde_json::json!({ "root": self.context() }),
+      None,
+    )?;
+    let mut frames = vec![];
+    collect_contexts(&tree["contexts"], &mut frames);
+
+    let existing = self.send(
+      "script.getRealms",
+      serde_json::json!({ "type": "window" }),
+      None,
+    )?;
+
+    let top = self.context().to_string();
+    let realm = move |params: &Value| ExecutionContext {
+      id: params["realm"].as_str().unwrap_or_default().to_string(),
+      origin: params["origin"].as_str().unwrap_or_default().to_string(),
+      top_level: params["context"] == top.as_str(),
+    };
+
+    // Sandboxes are realms of their own, but the page's scripts don't run in them
+    let is_ours = |params: &Value, frames: &[String]| {
+      params["type"] == "window"
+        && params["sandbox"].is_null()
+        && frames
+          .iter()
+          .any(|frame| params["context"] == frame.as_str())
+    };
+
+    {
+      let mut realms = self.realms.lock().unwrap();
+
+      for params in existing["realms"].as_array().into_iter().flatten() {
+        if is_ours(params, &frames) {
+          realms.push(realm(params));
+        }
+      }
+    }
+
+    let realms = self.realms.clone();
+
+    std::thread::spawn(move || {
+      for event in rx.iter() {
+        let params = &event.params;
+
+        match event.method.as_str() {
+          "browsingContext.contextCreated"
+            if frames
+              .iter()
+              .any(|frame| params["parent"] == frame.as_str()) =>
+          {
+            frames.push(params["context"].as_str().unwrap_or_default().to_string());
+          }
+          "browsingContext.contextDestroyed" => {
+            frames.retain(|frame| params["context"] != frame.as_str());
+          }
+          // Realms created while seeding can show up twice
+          "script.realmCreated" if is_ours(params, &frames) => {
+            let created = realm(params);
+            let mut realms = realms.lock().unwrap();
+
+            if !realms.contains(&created) {
+              realms.push(created);
+            }
+          }
+          "script.realmDestroyed" => {
+            let mut realms = realms.lock().unwrap();
+            realms.retain(|realm| params["realm"] != realm.id.as_str());
+          }
+          _ => {}
+        }
+      }
+    });
+
+    Ok(())
+  }
+
+  /// Send a command and return its `result`, or an error if the browser rejected it.
+  pub fn send(
+    &self,
+    method: impl AsRef<str>,
+    params: impl Serialize,
+    timeout: Option<std::time::Duration>,
+  ) -> Result<Value, CrowserError> {
+    // Unlike CDP, sessions are implicit, so there is never a session ID
+    let cmd = CDPCommand::new(method, params, None);
+    let result = self.conn.send(cmd, timeout)?;
+
+    if result["type"] == "error" {
+      return Err(CrowserError::BidiError(format!(
+        "{}: {}",
+        result["error"].as_str().unwrap_or("unknown error"),
+        result["message"].as_str().unwrap_or_default()
+      )));
+    }
+
+    Ok(result["result"].clone())
+  }
+
+  /// Subscribe to an event. Unlike CDP, BiDi only sends events that were subscribed to, so this tells the browser as well.
+  pub fn subscribe(
+    &self,
+    event: impl AsRef<str>,
+  ) -> Result<flume::Receiver<CDPEvent>, CrowserError> {
+    self.send(
+      "session.subscribe",
+      serde_json::json!({ "events": [event.as_ref()] }),
+      None,
+    )?;
+
+    Ok(self.conn.subscribe(event, None))
+  }
+
+  /// Like `subscribe`, but the browser only sends events about a browsing context and the iframes in it.
+  /// Other subscriptions on the connection may still bring in events from elsewhere, so check where they're from.
+  pub fn subscribe_in(
+    &self,
+    event: impl AsRef<str>,
+    context: impl AsRef<str>,
+  ) -> Result<flume::Receiver<CDPEvent>, CrowserError> {
+    self.send(
+      "session.subscribe",
+      serde_json::json!({ "events": [event.as_ref()], "contexts": [context.as_ref()] }),
+      None,
+    )?;
+
+    Ok(self.conn.subscribe(event, None))
+  }
+}
+
+/// The IDs of every browsing context in a `browsingContext.getTree` result, children included
+fn collect_contexts(contexts: &Value, ids: &mut Vec<String>) {
+  for context in contexts.as_array().into_iter().flatten() {
+    ids.push(context["context"].as_str().unwrap_or_default().to_string());
+    collect_contexts(&context["children"], ids);
+  }
+}
+
+/// Convert plain JSON into a BiDi `LocalValue`, for passing arguments to functions
+pub fn json_to_local_value(val: &Value) -> Value {
+  match val {
+    Value::Null => serde_json::json!({ "type": "null" }),
+    Value::Bool(val) => serde_json::json!({ "type": "boolean", "value": val }),
+    Value::Number(val) => serde_json::json!({ "type": "number", "value": val }),
+    Value::String(val) => serde_json::json!({ "type": "string", "value": val }),
+    Value::Array(items) => serde_json::json!({
+      "type": "array",
+      "value": items.iter().map(json_to_local_value).collect::<Vec<_>>(),
+    }),
+    Value::Object(map) => serde_json::json!({
+      "type": "object",
+      "value": map
+        .iter()
+        .map(|(key, val)| serde_json::json!([key, json_to_local_value(val)]))
+        .collect::<Vec<_>>(),
+    }),
+  }
+}
+
+/// Convert a BiDi `RemoteValue` into plain JSON, roughly like `JSON.stringify` would.
+/// Values without a JSON equivalent (functions, DOM nodes, `NaN`, etc.) become `null`.
+pub fn remote_value_to_json(val: &Value) -> Value {
+  let inner = &val["value"];
+
+  match val["type"].as_str().unwrap_or_default() {
+    "string" | "boolean" | "bigint" | "date" => inner.clone(),
+    // Special numbers (NaN, Infinity, -0) are sent as strings
+    "number" => match inner {
+      Value::String(special) if special == "-0" => Value::from(0),
+      Value::String(_) => Value::Null,
+      _ => inner.clone(),
+    },
+    "regexp" => Value::String(format!(
+      "/{}/{}",
+      inner["pattern"].as_str().unwrap_or_default(),
+      inner["flags"].as_str().unwrap_or_default()
+    )),
+    "array" | "set" => Value::Array(
+      inner
+        .as_array()
+        .map(|items| items.iter().map(remote_value_to_json).collect())
+        .unwrap_or_default(),
+    ),
+    // Objects and maps are lists of [key, value] pairs, where the key is either a string or another RemoteValue
+    "object" | "map" => {
+      let mut map = serde_json::Map::new();
+
+      for pair in inner.as_array().unwrap_or(&vec![]) {
+        let key = match &pair[0] {
+          Value::String(key) => key.clone(),
+          key => match remote_value_to_json(key) {
+            Value::String(key) => key,
+            key => key.to_string(),
+          },
+        };
+
+        map.insert(key, remote_value_to_json(&pair[1]));
+      }
+
+      Value::Object(map)
+    }
+    _ => Value::Null,
+  }
+}
@@ -0,0 +1,17 @@
+//! A pretend Chromium-based browser for testing `Window::create` without a real one. See `crowser::test_support`.
+
+use crowser::test_support::{run_until_closed, FakeBrowser};
+
+fn main() {
+  let port = std::env::args()
+    .find_map(|arg| {
+      arg
+        .strip_prefix("--remote-debugging-port=")
+        .and_then(|port| port.parse::<u16>().ok())
+    })
+    .expect("No --remote-debugging-port given");
+
+  let browser = FakeBrowser::start_on(port);
+
+  run_until_closed(&browser);
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use crate::{ContentConfig, Window};
+use crate::{Window, WindowState};
 
 /// In order to prevent profile collisions, the main user-provided profile directory is supplemented with additional folders.
 pub fn get_profile_dir(win: &Window) -> PathBuf {
@@ -29,13 +29,11 @@ pub fn generate_cli_options(win: &Window) -> Vec<String> {
 
     // Configurable stuff
     format!("--window-size={},{}", win.width, win.height),
-    match &win.config {
-      ContentConfig::Remote(config) => {
-        format!("--app={}", config.url)
-      },
-      ContentConfig::Local(_) => {
-        format!("--app=http://localhost:{}", win.webserver_port.unwrap())
-      },
+    // There's no app window to speak of in headless mode, so the URL is just opened in a tab
+    if win.headless {
+      win.start_url()
+    } else {
+      format!("--app={}", win.start_url())
     },
 
     // Profile
@@ -46,6 +44,36 @@ pub fn generate_cli_options(win: &Window) -> Vec<String> {
     },
   ]);
 
+  if win.headless {
+    options.push("--headless=new".to_string());
+  }
+
+  if let Some((x, y)) = win.position {
+    options.push(format!("--window-position={},{}", x, y));
+  }
+
+  match win.initial_state {
+    WindowState::Maximized => options.push("--start-maximized".to_string()),
+    WindowState::Fullscreen => options.push("--start-fullscreen".to_string()),
+    _ => {}
+  }
+
+  if win.kiosk {
+    options.extend([
+      "--kiosk".to_string(),
+      // No crash restore prompts, error dialogs, or swipe navigation for the user to get stuck in
+      "--disable-session-crashed-bubble".to_string(),
+      "--noerrdialogs".to_string(),
+      "--overscroll-history-navigation=0".to_string(),
+      "--disable-pinch".to_string(),
+    ]);
+  }
+
+  // App windows have no browser UI to begin with, and there's no switch for dropping the OS title bar
+  if win.frameless && !win.headless && !win.kiosk {
+    log::warn!(target: "browser", "Chromium app windows are always frameless, so set_frameless has no effect");
+  }
+
   if win.disable_hardware_acceleration {
     options.push("--disable-gpu".to_string());
   }
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use crate::{ContentConfig, Window};
+use crate::Window;
 
 /// In order to prevent profile collisions, the main user-provided profile directory is supplemented with additional folders.
 pub fn get_profile_dir(win: &Window) -> PathBuf {
@@ -21,13 +21,15 @@ pub fn generate_cli_options(win: &Window) -> Vec<String> {
 
   options.push("--url".to_string());
 
-  match &win.config {
-    ContentConfig::Remote(config) => {
-      options.push(config.url.clone());
-    }
-    ContentConfig::Local(_) => {
-      options.push(format!("http://localhost:{}", win.webserver_port.unwrap()));
-    }
+  options.push(win.start_url());
+
+  if win.kiosk {
+    options.push("--kiosk".to_string());
+  }
+
+  // `--window-size` only applies to `--screenshot`, so headless pages are sized once connected instead
+  if win.headless {
+    options.push("--headless".to_string());
   }
 
   // Profile directory
@@ -52,7 +54,7 @@ pub fn write_extra_profile_files(win: &Window) -> Result<(), std::io::Error> {
 
   user_css.push("userChrome.css");
 
-  let pref_str = format!(
+  let mut pref_str = format!(
     r#"
 user_pref("browser.startup.homepage", "about:blank");
 user_pref("browser.startup.page", 0);
@@ -70,12 +72,12 @@ user_pref('privacy.window.maxInnerHeight', {});
 user_pref('gfx.webrender.all', {});
 user_pref('layers.acceleration.force-enabled', {});
 
-// For IPC, enable devtools protocol and such
+// For IPC, enable the remote agent and such
 user_pref('devtools.chrome.enabled', true);
 user_pref('devtools.debugger.remote-enabled', true);
 user_pref('devtools.debugger.prompt-connection', false);
-// guess this will have to change to webdriver bidi soon
-user_pref('remote.active-protocols', 2);
+// 1 = WebDriver BiDi only. CDP is deprecated (and eventually removed) in Firefox
+user_pref('remote.active-protocols', 1);
 
 // Media (ie autoplay)
 user_pref('media.autoplay.blocking_policy', false);
@@ -85,6 +87,29 @@ user_pref("toolkit.legacyUserProfileCustomizations.stylesheets", true);
     win.width, win.height, !win.disable_hardware_acceleration, !win.disable_hardware_acceleration
   );
 
+  // Match what Chromium's kiosk flags do
+  if win.kiosk {
+    pref_str.push_str(
+      r#"
+user_pref("browser.sessionstore.resume_from_crash", false);
+user_pref("browser.gesture.swipe.left", "");
+user_pref("browser.gesture.swipe.right", "");
+user_pref("apz.allow_zooming", false);
+user_pref("browser.tabs.warnOnClose", false);
+    "#,
+    );
+  }
+
+  // Keep the OS title bar, since the tab strip that would otherwise be drawn in its place is hidden below
+  if win.frameless {
+    pref_str.push_str(
+      r#"
+user_pref("browser.tabs.inTitlebar", 0);
+user_pref("browser.tabs.drawInTitlebar", false);
+    "#,
+    );
+  }
+
   std::fs::write(prefs, pref_str)?;
 
   let mut css_str = r#"
@@ -95,11 +120,41 @@ user_pref("toolkit.legacyUserProfileCustomizations.stylesheets", true);
     "#
   .to_string();
 
+  if win.frameless {
+    css_str.push_str(
+      r#"
+    /* Hide the tab strip, leaving the title bar and the page like a Chromium app window */
+    #TabsToolbar {
+      visibility: collapse !important;
+    }
+    "#,
+    );
+  }
+
   if let Some(config) = &win.firefox_config {
     css_str.push_str(config.custom_css.as_ref().unwrap_or(&String::new()));
   }
 
   std::fs::write(user_css, css_str)?;
 
+  // Window icons are looked up by window type, falling back to "default"
+  if let Some(icon) = &win.icon {
+    let mut icon_dir = win.profile_directory.clone();
+    icon_dir.push("chrome");
+    icon_dir.push("icons");
+    icon_dir.push("default");
+
+    std::fs::create_dir_all(&icon_dir)?;
+
+    let extension = icon
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .unwrap_or("png");
+
+    for name in ["default", "main-window"] {
+      std::fs::copy(icon, icon_dir.join(format!("{}.{}", name, extension)))?;
+    }
+  }
+
   Ok(())
 }
@@ -6,7 +6,7 @@ This module contains everything needed to detect and specify different browser i
 While most of this functionality is handled for you, you can use these to get insight on any specific tweaks
 you may need to apply in your own project.
 
-```rust
+```rust,ignore
 let browser = get_best_browser(None);
 
 if let Some(browser) = browser {
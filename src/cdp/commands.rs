@@ -9,6 +9,8 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
+use crate::{ScreenshotFormat, WindowState};
+
 // "Master" struct that handles the structure of all commands
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -16,7 +18,6 @@ pub struct CDPCommand {
   pub method: String,
   pub params: serde_json::Value,
   pub session_id: Option<String>,
-  pub seen: Option<bool>,
 }
 
 impl CDPCommand {
@@ -25,7 +26,6 @@ impl CDPCommand {
       method: method.as_ref().to_string(),
       params: serde_json::to_value(params).unwrap(),
       session_id,
-      seen: Some(false),
     }
   }
 }
@@ -48,6 +48,26 @@ impl Display for CDPCommand {
   }
 }
 
+/// An event sent by the browser, i.e. a message without an `id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CDPEvent {
+  pub method: String,
+  #[serde(default)]
+  pub params: serde_json::Value,
+  pub session_id: Option<String>,
+}
+
+impl CDPEvent {
+  pub fn new(method: impl AsRef<str>, params: serde_json::Value) -> Self {
+    CDPEvent {
+      method: method.as_ref().to_string(),
+      params,
+      session_id: None,
+    }
+  }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CDPResponse {
   pub id: Option<usize>,
@@ -75,13 +95,32 @@ impl Display for CDPResponse {
 #[derive(Serialize, Deserialize)]
 pub struct PageEnable {}
 
-#[derive(Serialize, Deserialize)]
-pub struct PageDisable {}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct RuntimeEvaluate {
   pub expression: String,
+  /// The page's own context if not given
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub context_id: Option<i64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub await_promise: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub return_by_value: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeCallArgument {
+  pub value: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeCallFunctionOn {
+  pub function_declaration: String,
+  /// The function is called in this context like a plain function call, with no particular `this`
+  pub execution_context_id: i64,
+  pub arguments: Vec<RuntimeCallArgument>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub await_promise: Option<bool>,
   #[serde(skip_serializing_if = "Option::is_none")]
@@ -108,9 +147,135 @@ pub struct PageAddScriptToEvaluateOnNewDocument {
   pub source: String,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetSetAutoAttach {
+  pub auto_attach: bool,
+  pub wait_for_debugger_on_start: bool,
+  pub flatten: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PageReload {
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub ignore_cache: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub script_to_evaluate_on_load: Option<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageNavigate {
+  pub url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageGetNavigationHistory {}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageNavigateToHistoryEntry {
+  pub entry_id: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserGetWindowForTarget {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub target_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserBounds {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub left: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub top: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub width: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub height: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub window_state: Option<WindowState>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserSetWindowBounds {
+  pub window_id: i64,
+  pub bounds: BrowserBounds,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchRequestPattern {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub url_pattern: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub resource_type: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub request_stage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchEnable {
+  pub patterns: Vec<FetchRequestPattern>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchContinueRequest {
+  pub request_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchFailRequest {
+  pub request_id: String,
+  pub error_reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageViewport {
+  pub x: f64,
+  pub y: f64,
+  pub width: f64,
+  pub height: f64,
+  pub scale: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageCaptureScreenshot {
+  pub format: ScreenshotFormat,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub clip: Option<PageViewport>,
+  pub capture_beyond_viewport: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageGetLayoutMetrics {}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PagePrintToPdf {
+  pub landscape: bool,
+  pub display_header_footer: bool,
+  pub print_background: bool,
+  pub paper_width: f64,
+  pub paper_height: f64,
+  pub margin_top: f64,
+  pub margin_bottom: f64,
+  pub margin_left: f64,
+  pub margin_right: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub header_template: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub footer_template: Option<String>,
+}
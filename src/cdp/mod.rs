@@ -1,19 +1,51 @@
 use std::fmt::Debug;
 use std::{
-  collections::HashMap, sync::{Arc, Mutex}, vec
+  collections::HashMap,
+  io::{Read, Write},
+  net::TcpStream,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
 };
 
-use commands::CDPCommand;
+use commands::{CDPCommand, CDPEvent};
+use recording::{Direction, Recorder};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tungstenite::Message;
+use tungstenite::{client::IntoClientRequest, protocol::Role, Message, WebSocket};
 
 use crate::error::CrowserError;
 
 pub mod commands;
+pub mod recording;
 
-type CdpRegistrationMap =
-  Arc<Mutex<HashMap<String, Vec<Arc<Mutex<Box<dyn FnMut(&Cdp, Value) -> Result<Value, CrowserError> + Send + Sync>>>>>>>;
+/// How long to wait for a response when the caller doesn't specify a timeout
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Synthetic event dispatched when the websocket connection drops, with the reason in `params.reason`
+pub const CONNECTION_CLOSED_EVENT: &str = "Crowser.connectionClosed";
+
+/// Synthetic event dispatched when a frame from the browser can't be parsed, with `params.message` and `params.frame`
+pub const PROTOCOL_ERROR_EVENT: &str = "Crowser.protocolError";
+
+/// Subscribe to this method name to receive every event
+pub const ALL_EVENTS: &str = "*";
+
+/// How many events a subscriber can fall behind by. Past that, new events are dropped for it until it catches up, so a
+/// subscriber that stops reading can't grow without bound or hold up everyone else.
+const SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// How to set up a connection to the browser, besides where it is
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+  /// Record the session to this file, see `recording`
+  pub recording: Option<PathBuf>,
+  /// Log every frame sent and received, at trace level under the `cdp` target
+  pub tracing: bool,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -39,71 +71,159 @@ impl CDPMessageInternal {
 
 #[derive(Debug, Clone)]
 struct CDPMessenger {
-  tx: flume::Sender<String>,
-  rx: flume::Receiver<String>,
+  tx: flume::Sender<Message>,
+  rx: flume::Receiver<Message>,
 }
 
-#[derive(Debug, Clone)]
-struct CDPMessage {
-  result: Value,
+struct CDPSubscriber {
+  methods: Vec<String>,
+  session_id: Option<String>,
+  tx: flume::Sender<CDPEvent>,
+  /// Events dropped since the subscriber's queue filled up
+  dropped: usize,
+}
+
+impl CDPSubscriber {
+  fn matches(&self, event: &CDPEvent) -> bool {
+    self
+      .methods
+      .iter()
+      .any(|method| method == ALL_EVENTS || *method == event.method)
+      && (self.session_id.is_none() || self.session_id == event.session_id)
+  }
 }
 
-#[derive(Clone)]
 struct CDPIpcManager {
-  session_messages: HashMap<String, CDPMessage>,
-  events: Vec<CDPCommand>,
-  responders: CdpRegistrationMap,
+  /// One-shot senders for in-flight commands, keyed by command ID. Entries are removed once the response is delivered or the caller gives up.
+  pending: HashMap<usize, flume::Sender<Value>>,
+  connected: bool,
+  subscribers: Vec<CDPSubscriber>,
 }
 
 impl Debug for CDPIpcManager {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.debug_struct("CDPIpcManager")
-      .field("session_messages", &self.session_messages)
-      .field("events", &self.events)
+      .field("pending", &self.pending.keys().collect::<Vec<&usize>>())
+      .field("connected", &self.connected)
+      .field(
+        "subscribers",
+        &self
+          .subscribers
+          .iter()
+          .map(|s| &s.methods)
+          .collect::<Vec<&Vec<String>>>(),
+      )
       .finish()
   }
 }
 
 #[derive(Debug, Clone)]
 pub struct Cdp {
-  cmd_id: usize,
+  // Shared between clones so two handles never hand out the same ID
+  cmd_id: Arc<AtomicUsize>,
   cmd: CDPMessenger,
   manager: Arc<Mutex<CDPIpcManager>>,
-  pub initialization_script: String,
+  recorder: Option<Arc<Mutex<Recorder>>>,
+  tracing: bool,
+}
+
+impl Default for Cdp {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Cdp {
-  pub fn new(intialization_script: impl AsRef<str>) -> Self {
+  pub fn new() -> Self {
     let (cmd_tx, cmd_rx) = flume::unbounded();
 
     Cdp {
-      cmd_id: 0,
+      cmd_id: Arc::new(AtomicUsize::new(0)),
       cmd: CDPMessenger {
         tx: cmd_tx,
         rx: cmd_rx,
       },
-      initialization_script: intialization_script.as_ref().to_string(),
       manager: Arc::new(Mutex::new(CDPIpcManager {
-        session_messages: HashMap::new(),
-        events: Vec::new(),
-        responders: Arc::new(Mutex::new(HashMap::new())),
+        pending: HashMap::new(),
+        connected: false,
+        subscribers: Vec::new(),
       })),
+      recorder: None,
+      tracing: false,
     }
   }
 
-  pub fn on(&mut self, name: impl AsRef<str>, callback: impl FnMut(&Cdp, Value) -> Result<Value, CrowserError> + Send + Sync + 'static) -> Result<(), CrowserError> {
-    let manager = self.manager.lock().unwrap();
-    let mut responders = manager.responders.lock().unwrap();
+  /// Log every frame sent and received, at trace level under the `cdp` target. Only affects connections made after this is called.
+  pub fn set_tracing(&mut self, tracing: bool) {
+    self.tracing = tracing;
+  }
+
+  /// Record every frame sent and received to a JSONL file, for debugging or replaying later with `Cdp::replay`.
+  /// Only frames from connections made after this is called are recorded.
+  pub fn record_to(&mut self, path: impl AsRef<Path>) -> Result<(), CrowserError> {
+    self.recorder = Some(Arc::new(Mutex::new(Recorder::create(path)?)));
+
+    Ok(())
+  }
+
+  /// Play back a session recorded with `Cdp::record_to`, instead of connecting to a browser. See `recording` for how
+  /// commands are matched up with the recording.
+  pub fn replay(path: impl AsRef<Path>) -> Result<Self, CrowserError> {
+    let frames = recording::load(path)?;
+    let cdp = Cdp::new();
 
-    // Check if command already exists
-    if responders.contains_key(name.as_ref()) {
-      // Throw error
-      return Err(CrowserError::IpcError("Command already exists".to_string()));
+    recording::replay_executor(&cdp, frames, cdp.cmd.rx.clone());
+
+    Ok(cdp)
+  }
+
+  fn record(&self, direction: Direction, text: &str) {
+    if self.tracing {
+      match direction {
+        Direction::Sent => log::trace!(target: "cdp", "Sent: {}", text),
+        Direction::Received => log::trace!(target: "cdp", "Received: {}", text),
+        Direction::Closed => {}
+      }
     }
 
-    responders.insert(name.as_ref().to_string(), vec![Arc::new(Mutex::new(Box::new(callback)))]);
+    if let Some(recorder) = &self.recorder {
+      recorder.lock().unwrap().record(direction, text);
+    }
+  }
 
-    Ok(())
+  /// Subscribe to events with the given method name (or `ALL_EVENTS`), optionally only those from a specific session.
+  /// Events are delivered in the order they were received. Dropping the receiver unsubscribes.
+  ///
+  /// Receivers hold up to `SUBSCRIBER_CAPACITY` events. Once full, new events are dropped (with a warning) until the
+  /// receiver is read from again, so keep up with the events or drop the receiver.
+  pub fn subscribe(
+    &self,
+    method: impl AsRef<str>,
+    session_id: Option<String>,
+  ) -> flume::Receiver<CDPEvent> {
+    self.subscribe_many(&[method.as_ref()], session_id)
+  }
+
+  /// Like `subscribe`, but to several methods at once, delivered in the order they were received
+  pub fn subscribe_many(
+    &self,
+    methods: &[&str],
+    session_id: Option<String>,
+  ) -> flume::Receiver<CDPEvent> {
+    let (tx, rx) = flume::bounded(SUBSCRIBER_CAPACITY);
+    let mut manager = self.manager.lock().unwrap();
+
+    // If the connection is already gone, returning a receiver with no sender tells the caller immediately
+    if manager.connected {
+      manager.subscribers.push(CDPSubscriber {
+        methods: methods.iter().map(|method| method.to_string()).collect(),
+        session_id,
+        tx,
+        dropped: 0,
+      });
+    }
+
+    rx
   }
 
   pub fn connect(&mut self, port: u16) -> Result<(), CrowserError> {
@@ -127,13 +247,12 @@ impl Cdp {
       ));
     }
 
-    let rx = self.cmd.rx.clone();
-    let manager = self.manager.clone();
-    let mut cdp = self.clone();
-
-    std::thread::spawn(move || ws_executor(&mut cdp, manager, ws_url, rx));
+    self.connect_url(ws_url)
+  }
 
-    Ok(())
+  /// Connect directly to a websocket URL, skipping the `/json/version` lookup
+  pub fn connect_url(&mut self, url: impl AsRef<str>) -> Result<(), CrowserError> {
+    ws_executor(self, url, self.cmd.rx.clone())
   }
 
   // pub fn poll(&mut self) -> Result<String, CrowserError> {
@@ -144,193 +263,278 @@ impl Cdp {
   //     .map_err(|_| CrowserError::CDPError("Could not receive message".to_string()))
   // }
 
-  pub fn send(
-    &mut self,
-    cmd: CDPCommand,
-    timeout: Option<std::time::Duration>,
-  ) -> Result<Value, CrowserError> {
-    let manager = self.manager.clone();
-    let msg = serde_json::to_string(&CDPMessageInternal::new(self.cmd_id + 1, cmd));
+  pub fn send(&self, cmd: CDPCommand, timeout: Option<Duration>) -> Result<Value, CrowserError> {
+    let id = self.cmd_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let msg = serde_json::to_string(&CDPMessageInternal::new(id, cmd));
     let msg = msg.map_err(|e| {
       CrowserError::CDPError("Could not serialize message: ".to_string() + &e.to_string())
     })?;
 
-    self.cmd_id += 1;
+    // Register interest in the response before sending, so a fast response can't slip past us
+    let (tx, rx) = flume::bounded(1);
+    {
+      let mut manager = self.manager.lock().unwrap();
 
-    self.cmd.tx.send(msg).map_err(|e| {
-      CrowserError::CDPError("Could not send message: ".to_string() + &e.to_string())
-    })?;
+      if !manager.connected {
+        return Err(CrowserError::CDPError(
+          "Not connected to a browser".to_string(),
+        ));
+      }
 
-    let id = self.cmd_id;
-    // Wait for response to be recieved
-    let wait_thread = std::thread::spawn(move || {
-      let timeout = timeout.unwrap_or(std::time::Duration::from_secs(1));
-      let now = std::time::Instant::now();
-
-      // Wait for a response
-      loop {
-        // Wait an amount of time, to prevent a tight loop
-        std::thread::sleep(std::time::Duration::from_millis(1));
-
-        match manager.try_lock() {
-          Ok(manager) => {
-            if let Some(msg) = manager.session_messages.get(&id.to_string()) {
-              return Ok(msg.result.clone());
-            }
-          }
-          Err(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
-        }
+      manager.pending.insert(id, tx);
+    }
 
-        if now.elapsed().as_millis() > timeout.as_millis() {
-          return Err(CrowserError::CDPError(
-            "Timeout waiting for response".to_string(),
-          ));
-        }
-      }
-    });
+    if let Err(e) = self.cmd.tx.send(Message::Text(msg)) {
+      self.manager.lock().unwrap().pending.remove(&id);
+      return Err(CrowserError::CDPError(
+        "Could not send message: ".to_string() + &e.to_string(),
+      ));
+    }
 
-    match wait_thread.join() {
-      Ok(val) => val,
-      Err(err) => Err(err.into()),
+    match rx.recv_timeout(timeout.unwrap_or(DEFAULT_TIMEOUT)) {
+      Ok(val) => Ok(val),
+      Err(flume::RecvTimeoutError::Timeout) => {
+        // Nobody is waiting anymore, so don't let a late response sit around forever
+        self.manager.lock().unwrap().pending.remove(&id);
+        Err(CrowserError::Timeout(
+          "Timeout waiting for response".to_string(),
+        ))
+      }
+      Err(flume::RecvTimeoutError::Disconnected) => Err(CrowserError::CDPError(
+        "Connection closed before a response was received".to_string(),
+      )),
     }
   }
+}
 
-  pub fn events(&mut self) -> Result<Vec<CDPCommand>, CrowserError> {
-    let manager = self.manager.clone();
-    let manager = manager.lock().unwrap();
+/// Read side of the websocket. Once the handshake is done, writes are discarded so that only the writer thread ever
+/// puts frames on the wire; anything tungstenite would reply with on its own (pongs, close acknowledgements) is forwarded
+/// to the writer instead.
+struct ReadOnlyStream {
+  stream: TcpStream,
+  handshake_done: bool,
+}
 
-    Ok(manager.events.clone())
+impl Read for ReadOnlyStream {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.stream.read(buf)
   }
+}
 
-  pub fn last_event_by_name(&mut self, name: &str) -> Result<Option<CDPCommand>, CrowserError> {
-    let events = self.events()?;
+impl Write for ReadOnlyStream {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    match self.handshake_done {
+      true => Ok(buf.len()),
+      false => self.stream.write(buf),
+    }
+  }
 
-    for event in events.iter().rev() {
-      if event.method == name {
-        return Ok(Some(event.clone()));
-      }
+  fn flush(&mut self) -> std::io::Result<()> {
+    match self.handshake_done {
+      true => Ok(()),
+      false => self.stream.flush(),
     }
-    Ok(None)
   }
+}
 
-  // pub fn all_events_by_name(&mut self, name: &str) -> Result<Vec<CDPCommand>, CrowserError> {
-  //   let events = self.events();
-  //   let mut new_events = vec![];
+/// Connect to the browser and start the reader and writer threads. Both block on I/O (the socket and the command
+/// channel respectively), so nothing spins while the connection is idle.
+fn ws_executor(
+  cdp: &Cdp,
+  url: impl AsRef<str>,
+  rx: flume::Receiver<Message>,
+) -> Result<(), CrowserError> {
+  let connect_error =
+    |err: String| CrowserError::CDPError("Could not connect to browser: ".to_string() + &err);
+  let request = url
+    .as_ref()
+    .into_client_request()
+    .map_err(|err| connect_error(err.to_string()))?;
+  let uri = request.uri();
 
-  //   for event in events? {
-  //     if event.method == name {
-  //       new_events.push(event);
-  //     }
-  //   }
+  if uri.scheme_str() != Some("ws") {
+    return Err(CrowserError::CDPError(
+      "TLS websocket connections are not supported".to_string(),
+    ));
+  }
 
-  //   Ok(new_events)
-  // }
+  let stream = TcpStream::connect((uri.host().unwrap_or_default(), uri.port_u16().unwrap_or(80)))?;
 
-  pub fn wait_for_event(
-    &mut self,
-    name: &str,
-    timeout: Option<std::time::Duration>,
-  ) -> Result<CDPCommand, CrowserError> {
-    let timeout = timeout.unwrap_or(std::time::Duration::from_secs(1));
-    let now = std::time::Instant::now();
+  // The handshake happens on the reader, which keeps whatever arrived right behind the handshake response
+  let (mut reader, _) = tungstenite::client(
+    request,
+    ReadOnlyStream {
+      stream: stream.try_clone()?,
+      handshake_done: false,
+    },
+  )
+  .map_err(|err| connect_error(err.to_string()))?;
+  reader.get_mut().handshake_done = true;
 
-    loop {
-      std::thread::sleep(std::time::Duration::from_millis(100));
+  let mut writer = WebSocket::from_raw_socket(stream, Role::Client, None);
 
-      let mut events = self.events()?;
+  let t_cdp = cdp.clone();
+  std::thread::spawn(move || {
+    for msg in rx.iter() {
+      let closing = msg.is_close();
 
-      for event in events.iter_mut() {
-        if event.method == name && !event.seen.unwrap_or(false) {
-          event.seen = Some(true);
-          return Ok(event.clone());
-        }
+      if let Message::Text(text) = &msg {
+        t_cdp.record(Direction::Sent, text);
       }
 
-      if timeout.as_millis() > 0 && now.elapsed().as_millis() > timeout.as_millis() {
-        return Err(CrowserError::CDPError(
-          "Timeout waiting for event".to_string(),
-        ));
+      if writer.send(msg).is_err() || closing {
+        break;
       }
     }
-  }
+  });
+
+  cdp.manager.lock().unwrap().connected = true;
+  log::debug!(target: "cdp", "Connected to {}", url.as_ref());
+
+  let cdp = cdp.clone();
+  std::thread::spawn(move || {
+    let reason = loop {
+      let msg = match reader.read() {
+        Ok(val) => val,
+        Err(err) => break err.to_string(),
+      };
+
+      match msg {
+        Message::Text(text) => handle_frame(&cdp, text),
+        Message::Binary(data) => match String::from_utf8(data) {
+          Ok(text) => handle_frame(&cdp, text),
+          Err(err) => protocol_error(&cdp, err.to_string(), ""),
+        },
+        Message::Ping(data) => {
+          cdp.cmd.tx.send(Message::Pong(data)).unwrap_or_default();
+        }
+        Message::Close(frame) => {
+          break match frame {
+            Some(frame) => format!("Browser closed the connection: {}", frame.reason),
+            None => "Browser closed the connection".to_string(),
+          };
+        }
+        _ => {}
+      }
+    };
+
+    disconnect(&cdp, reason);
+  });
+
+  Ok(())
 }
 
-fn ws_executor(
-  cdp: &mut Cdp,
-  manager: Arc<Mutex<CDPIpcManager>>,
-  url: impl AsRef<str>,
-  rx: flume::Receiver<String>,
-) -> Result<(), CrowserError> {
-  let (mut ws, _) = match tungstenite::connect(url.as_ref()) {
+/// Route a single incoming frame to either a waiting `send` or the event responders
+fn handle_frame(cdp: &Cdp, text: String) {
+  cdp.record(Direction::Received, &text);
+
+  let msg: Value = match serde_json::from_str(&text) {
     Ok(val) => val,
-    Err(err) => {
-      return Err(CrowserError::CDPError(
-        "Could not connect to browser: ".to_string() + &err.to_string(),
-      ))
-    }
+    Err(err) => return protocol_error(cdp, err.to_string(), &text),
   };
 
-  // Make the socket non-blocking so we can recieve and send in the same loop
-  match ws.get_mut() {
-    tungstenite::stream::MaybeTlsStream::Plain(val) => val.set_nonblocking(true),
-    _ => unimplemented!(),
-  }?;
+  // If it doesn't have an ID, it's an event, otherwise it's a response
+  if msg["id"].is_null() {
+    match serde_json::from_value::<CDPEvent>(msg) {
+      Ok(event) => dispatch_event(cdp, event),
+      Err(err) => protocol_error(cdp, err.to_string(), &text),
+    }
+
+    return;
+  }
 
-  loop {
-    // Small delay to prevent a tight loop
-    std::thread::sleep(std::time::Duration::from_millis(1));
+  let id = match msg["id"].as_u64() {
+    Some(id) => id as usize,
+    None => return protocol_error(cdp, "Response ID is not a number".to_string(), &text),
+  };
+  let mut manager = cdp.manager.lock().unwrap();
 
-    // This is non-blocking so it should be fine
-    let msg = match ws.read() {
-      Ok(val) => val,
-      Err(_) => Message::Binary(vec![]),
-    };
-    let cmd = rx.try_recv().unwrap_or_default();
+  // If the caller already timed out there is nobody to deliver to, and the response is simply dropped
+  if let Some(tx) = manager.pending.remove(&id) {
+    tx.send(msg).unwrap_or_default();
+  }
+}
+
+fn dispatch_event(cdp: &Cdp, event: CDPEvent) {
+  let mut manager = cdp.manager.lock().unwrap();
 
-    if !cmd.is_empty() {
-      //println!("> {:?}", cmd);
-      ws.send(cmd.into()).map_err(|e| {
-        CrowserError::CDPError("Could not send command: ".to_string() + &e.to_string())
-      })?;
+  // A disconnected receiver was dropped, so that subscriber is removed. A full one is behind, and misses the event
+  manager.subscribers.retain_mut(|sub| {
+    if !sub.matches(&event) {
+      return true;
     }
 
-    if !msg.is_empty() {
-      let mut messages = manager.lock().unwrap();
-      let msg: Value = serde_json::from_str(&msg.to_string()).unwrap();
-
-      // If it doesn't have an ID, it's an event, otherwise it's a response
-      if msg["id"].is_null() {
-        // println!("! {}", msg);
-        messages.events.push(CDPCommand::from(msg.to_string()));
-
-        // Run callback if it exists
-        let mut responders = messages.responders.lock().unwrap();
-
-        if let Some(responders) = responders.get_mut(msg["method"].as_str().unwrap_or_default()) {
-          for callback in responders.iter_mut() {
-            let callback = callback.clone();
-            let cdp = cdp.clone();
-            let msg = msg.clone();
-
-            // Run callback in a new thread to prevent blocking
-            std::thread::spawn(move || {
-              let mut cb = callback.lock().unwrap();
-              cb(&cdp, msg["params"].clone()).unwrap_or_default();
-            });
-          }
+    match sub.tx.try_send(event.clone()) {
+      Ok(_) => {
+        if sub.dropped > 0 {
+          log::warn!(target: "cdp", "Subscriber to {:?} caught up after missing {} events", sub.methods, sub.dropped);
+          sub.dropped = 0;
         }
-      } else {
-        //println!("< {}", msg);
-        messages
-          .session_messages
-          .insert(msg["id"].to_string(), CDPMessage { result: msg });
+
+        true
       }
+      Err(flume::TrySendError::Full(_)) => {
+        if sub.dropped == 0 {
+          log::warn!(target: "cdp", "Subscriber to {:?} isn't keeping up, dropping its events until it does", sub.methods);
+        }
+
+        sub.dropped += 1;
+        true
+      }
+      Err(flume::TrySendError::Disconnected(_)) => false,
     }
+  });
+}
+
+/// Report a frame we couldn't make sense of to anyone listening for `PROTOCOL_ERROR_EVENT`
+fn protocol_error(cdp: &Cdp, message: String, frame: &str) {
+  log::warn!(target: "cdp", "Protocol error: {}", message);
+
+  dispatch_event(
+    cdp,
+    CDPEvent::new(
+      PROTOCOL_ERROR_EVENT,
+      serde_json::json!({ "message": message, "frame": frame }),
+    ),
+  );
+}
+
+/// Mark the connection as dead, fail every in-flight command, and let subscribers know
+fn disconnect(cdp: &Cdp, reason: String) {
+  cdp.record(Direction::Closed, &reason);
+  log::debug!(target: "cdp", "Connection closed: {}", reason);
+
+  {
+    let mut manager = cdp.manager.lock().unwrap();
+    manager.connected = false;
+
+    // Dropping the senders wakes up anyone waiting in `send`
+    manager.pending.clear();
   }
+
+  // Stop the writer thread as well
+  cdp.cmd.tx.send(Message::Close(None)).unwrap_or_default();
+
+  dispatch_event(
+    cdp,
+    CDPEvent::new(
+      CONNECTION_CLOSED_EVENT,
+      serde_json::json!({ "reason": reason }),
+    ),
+  );
+
+  // Nothing else is coming, so disconnect every receiver
+  cdp.manager.lock().unwrap().subscribers.clear();
 }
 
-pub fn launch(port: u16, initialization_script: impl AsRef<str>) -> Result<Cdp, CrowserError> {
-  let mut cdp = Cdp::new(initialization_script);
+pub fn launch(port: u16, options: &ConnectionOptions) -> Result<Cdp, CrowserError> {
+  let mut cdp = Cdp::new();
+  cdp.set_tracing(options.tracing);
+
+  if let Some(path) = &options.recording {
+    cdp.record_to(path)?;
+  }
+
   cdp.connect(port)?;
   Ok(cdp)
 }
@@ -370,3 +574,125 @@ fn attempt_get_ws_url(contents: impl AsRef<str>) -> Result<String, CrowserError>
 
   Ok(val)
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::test_support::FakeBrowser;
+
+  #[test]
+  fn send_returns_response() {
+    let browser = FakeBrowser::start();
+    browser.on("Test.echo", |cmd| Ok(cmd.params.clone()));
+
+    let cdp = launch(browser.port(), &ConnectionOptions::default()).unwrap();
+    let cmd = CDPCommand::new("Test.echo", serde_json::json!({ "hello": "world" }), None);
+    let response = cdp.send(cmd, None).unwrap();
+
+    assert_eq!(response["result"]["hello"], "world");
+  }
+
+  #[test]
+  fn send_times_out() {
+    let browser = FakeBrowser::start();
+    browser.on("Test.slow", |_| {
+      std::thread::sleep(Duration::from_millis(500));
+      Ok(Value::Null)
+    });
+
+    let cdp = launch(browser.port(), &ConnectionOptions::default()).unwrap();
+    let cmd = CDPCommand::new("Test.slow", Value::Null, None);
+
+    assert!(matches!(
+      cdp.send(cmd, Some(Duration::from_millis(50))),
+      Err(CrowserError::Timeout(_))
+    ));
+  }
+
+  #[test]
+  fn subscribe_filters_by_method_and_session() {
+    let browser = FakeBrowser::start();
+    let cdp = launch(browser.port(), &ConnectionOptions::default()).unwrap();
+
+    let any_session = cdp.subscribe("Test.event", None);
+    let one_session = cdp.subscribe("Test.event", Some("a".to_string()));
+    let everything = cdp.subscribe(ALL_EVENTS, None);
+
+    browser.emit("Test.other", Value::Null, None);
+    browser.emit("Test.event", serde_json::json!({ "n": 1 }), Some("b"));
+    browser.emit("Test.event", serde_json::json!({ "n": 2 }), Some("a"));
+
+    let timeout = Duration::from_secs(1);
+    assert_eq!(any_session.recv_timeout(timeout).unwrap().params["n"], 1);
+    assert_eq!(any_session.recv_timeout(timeout).unwrap().params["n"], 2);
+    assert_eq!(one_session.recv_timeout(timeout).unwrap().params["n"], 2);
+    assert_eq!(
+      everything.recv_timeout(timeout).unwrap().method,
+      "Test.other"
+    );
+  }
+
+  #[test]
+  fn full_subscribers_miss_new_events() {
+    let browser = FakeBrowser::start();
+    let cdp = launch(browser.port(), &ConnectionOptions::default()).unwrap();
+
+    let slow = cdp.subscribe("Test.event", None);
+    let closed = cdp.subscribe(CONNECTION_CLOSED_EVENT, None);
+
+    for n in 0..SUBSCRIBER_CAPACITY + 10 {
+      browser.emit("Test.event", serde_json::json!({ "n": n }), None);
+    }
+
+    // Disconnecting comes after every event, and ends the subscription once the queue is drained
+    browser.disconnect();
+    closed.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let received = slow.iter().collect::<Vec<_>>();
+    assert_eq!(received.len(), SUBSCRIBER_CAPACITY);
+    assert_eq!(received[0].params["n"], 0);
+    assert_eq!(
+      received[SUBSCRIBER_CAPACITY - 1].params["n"],
+      SUBSCRIBER_CAPACITY - 1
+    );
+  }
+
+  #[test]
+  fn frames_right_behind_the_handshake_arrive() {
+    let browser = FakeBrowser::start();
+    browser.greet("Test.greeting", Value::Null);
+
+    // Nothing can subscribe before the connection is up, so the recording shows what was received
+    let path = std::env::temp_dir().join(format!("crowser-handshake-{}.jsonl", std::process::id()));
+    let options = ConnectionOptions {
+      recording: Some(path.clone()),
+      tracing: false,
+    };
+    let cdp = launch(browser.port(), &options).unwrap();
+    let closed = cdp.subscribe(CONNECTION_CLOSED_EVENT, None);
+
+    browser.disconnect();
+    closed.recv_timeout(Duration::from_secs(1)).unwrap();
+
+    let recording = std::fs::read_to_string(&path).unwrap_or_default();
+    std::fs::remove_file(&path).unwrap_or_default();
+    assert!(recording.contains("Test.greeting"));
+  }
+
+  #[test]
+  fn disconnect_notifies_subscribers_and_fails_sends() {
+    let browser = FakeBrowser::start();
+    let cdp = launch(browser.port(), &ConnectionOptions::default()).unwrap();
+    let closed = cdp.subscribe(CONNECTION_CLOSED_EVENT, None);
+
+    browser.disconnect();
+
+    let event = closed.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(event.method, CONNECTION_CLOSED_EVENT);
+
+    let cmd = CDPCommand::new("Test.anything", Value::Null, None);
+    assert!(cdp.send(cmd, None).is_err());
+  }
+}
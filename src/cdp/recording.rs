@@ -0,0 +1,385 @@
+/*!
+Recording CDP sessions, and replaying them without a browser.
+
+A recording is a JSONL file with one line per frame, in the order they went over the wire:
+
+```json
+{"time":12.5,"direction":"sent","message":{"id":1,"method":"Target.getTargets"}}
+{"time":13.1,"direction":"received","message":{"id":1,"result":{"targetInfos":[]}}}
+{"time":950.2,"direction":"closed","message":"Browser closed the connection"}
+```
+
+`time` is in milliseconds since the connection was opened. Frames that aren't valid JSON are kept as strings.
+
+When replaying, every command is answered with the response to the matching command in the recording (same method and
+session, and preferably the same params), and recorded events are played back in between, in their original order.
+Commands that aren't in the recording get an error response.
+*/
+
+use std::{
+  fs::File,
+  io::{BufRead, BufReader, Write},
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tungstenite::Message;
+
+use super::{disconnect, handle_frame, Cdp};
+use crate::error::CrowserError;
+
+/// If the code being replayed stops sending the commands the recording expects, the rest of the recording plays out after this long
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+  Sent,
+  Received,
+  /// The connection closed, with the reason as the message
+  Closed,
+}
+
+/// A single line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedFrame {
+  pub time: f64,
+  pub direction: Direction,
+  pub message: Value,
+}
+
+impl RecordedFrame {
+  /// The frame as it went over the wire
+  fn text(&self) -> String {
+    match &self.message {
+      Value::String(text) => text.clone(),
+      message => message.to_string(),
+    }
+  }
+}
+
+/// Writes frames to a recording as they happen
+#[derive(Debug)]
+pub(crate) struct Recorder {
+  file: File,
+  start: Instant,
+}
+
+impl Recorder {
+  pub fn create(path: impl AsRef<Path>) -> Result<Self, CrowserError> {
+    Ok(Recorder {
+      file: File::create(path)?,
+      start: Instant::now(),
+    })
+  }
+
+  /// Write a frame straight to disk, so nothing is lost if the app crashes
+  pub fn record(&mut self, direction: Direction, text: &str) {
+    let frame = RecordedFrame {
+      time: self.start.elapsed().as_secs_f64() * 1000.0,
+      direction,
+      message: serde_json::from_str(text).unwrap_or_else(|_| Value::from(text)),
+    };
+
+    if let Ok(line) = serde_json::to_string(&frame) {
+      self
+        .file
+        .write_all(format!("{}\n", line).as_bytes())
+        .unwrap_or_default();
+    }
+  }
+}
+
+/// Read every frame of a recording
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<RecordedFrame>, CrowserError> {
+  let file = File::open(path)?;
+  let mut frames = vec![];
+
+  for (number, line) in BufReader::new(file).lines().enumerate() {
+    let line = line?;
+
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    frames.push(serde_json::from_str(&line).map_err(|err| {
+      CrowserError::CDPError(format!("Recording line {} is invalid: {}", number + 1, err))
+    })?);
+  }
+
+  Ok(frames)
+}
+
+/// Where the replay is up to. Frames are consumed as they're matched or played back, which doesn't have to be in order,
+/// since commands from different threads can interleave differently than they did when recording.
+struct Replay {
+  frames: Vec<RecordedFrame>,
+  consumed: Vec<bool>,
+}
+
+impl Replay {
+  fn new(frames: Vec<RecordedFrame>) -> Self {
+    let consumed = vec![false; frames.len()];
+
+    Replay { frames, consumed }
+  }
+
+  /// Answer a live command with its recorded response. Returns whether it was in the recording.
+  fn respond(&mut self, cdp: &Cdp, text: &str) -> bool {
+    let command: Value = match serde_json::from_str(text) {
+      Ok(command) => command,
+      Err(_) => return false,
+    };
+
+    let same_command = |i: usize| {
+      let frame = &self.frames[i];
+
+      !self.consumed[i]
+        && frame.direction == Direction::Sent
+        && frame.message["method"] == command["method"]
+        && frame.message["sessionId"] == command["sessionId"]
+    };
+
+    // Params can differ a little between versions of the app (a changed preload script, say), but which script is being
+    // evaluated is the whole point of an evaluate, so those have to match exactly
+    let exact = (0..self.frames.len())
+      .find(|&i| same_command(i) && self.frames[i].message["params"] == command["params"]);
+    let sent = match command["method"].as_str() {
+      Some("Runtime.evaluate" | "Runtime.callFunctionOn") => exact,
+      _ => exact.or_else(|| (0..self.frames.len()).find(|&i| same_command(i))),
+    };
+
+    let sent = match sent {
+      Some(sent) => sent,
+      None => {
+        let mut response = serde_json::json!({
+          "id": command["id"],
+          "error": {
+            "code": -32000,
+            "message": format!("{} is not in the recording", command["method"]),
+          },
+        });
+
+        if !command["sessionId"].is_null() {
+          response["sessionId"] = command["sessionId"].clone();
+        }
+
+        handle_frame(cdp, response.to_string());
+        return false;
+      }
+    };
+
+    self.consumed[sent] = true;
+
+    // Whatever the browser sent before this command was sent comes first
+    for i in 0..sent {
+      if !self.consumed[i] && self.is_event(i) {
+        self.consumed[i] = true;
+        handle_frame(cdp, self.frames[i].text());
+      }
+    }
+
+    let recorded_id = self.frames[sent].message["id"].clone();
+    let response = (sent..self.frames.len()).find(|&i| {
+      !self.consumed[i]
+        && self.frames[i].direction == Direction::Received
+        && self.frames[i].message["id"] == recorded_id
+    });
+
+    if let Some(response) = response {
+      self.consumed[response] = true;
+
+      let mut message = self.frames[response].message.clone();
+      message["id"] = command["id"].clone();
+      handle_frame(cdp, message.to_string());
+    }
+
+    true
+  }
+
+  /// Play back events up to the next command the recording is waiting for. Returns the reason if the connection closes.
+  fn play_events(&mut self, cdp: &Cdp) -> Option<String> {
+    for i in 0..self.frames.len() {
+      if self.consumed[i] {
+        continue;
+      }
+
+      match self.frames[i].direction {
+        Direction::Sent => return None,
+        Direction::Closed => {
+          self.consumed[i] = true;
+          return Some(self.frames[i].text());
+        }
+        // Responses to commands that never came are left where they are
+        Direction::Received if self.is_event(i) => {
+          self.consumed[i] = true;
+          handle_frame(cdp, self.frames[i].text());
+        }
+        Direction::Received => {}
+      }
+    }
+
+    None
+  }
+
+  /// Give up on the next command the recording is waiting for
+  fn skip_command(&mut self) -> bool {
+    let next = (0..self.frames.len())
+      .find(|&i| !self.consumed[i] && self.frames[i].direction == Direction::Sent);
+
+    match next {
+      Some(i) => {
+        self.consumed[i] = true;
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn is_event(&self, i: usize) -> bool {
+    self.frames[i].direction == Direction::Received && self.frames[i].message["id"].is_null()
+  }
+}
+
+/// Stand in for `ws_executor`, answering commands from the recording instead of a browser
+pub(crate) fn replay_executor(cdp: &Cdp, frames: Vec<RecordedFrame>, rx: flume::Receiver<Message>) {
+  cdp.manager.lock().unwrap().connected = true;
+
+  let cdp = cdp.clone();
+  std::thread::spawn(move || {
+    let mut replay = Replay::new(frames);
+    let mut last_match = Instant::now();
+
+    let reason = loop {
+      if let Some(reason) = replay.play_events(&cdp) {
+        break reason;
+      }
+
+      match rx.recv_timeout(IDLE_TIMEOUT.saturating_sub(last_match.elapsed())) {
+        Ok(Message::Text(text)) => {
+          if replay.respond(&cdp, &text) {
+            last_match = Instant::now();
+          }
+        }
+        Ok(Message::Close(_)) | Err(flume::RecvTimeoutError::Disconnected) => {
+          break "Replay stopped".to_string()
+        }
+        Ok(_) => {}
+        Err(flume::RecvTimeoutError::Timeout) => {
+          if !replay.skip_command() {
+            break "End of recording".to_string();
+          }
+
+          last_match = Instant::now();
+        }
+      }
+    };
+
+    disconnect(&cdp, reason);
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{
+    cdp::{commands::CDPCommand, launch, ConnectionOptions, CONNECTION_CLOSED_EVENT},
+    test_support::FakeBrowser,
+  };
+
+  fn recording_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+      "crowser-recording-{}-{}.jsonl",
+      std::process::id(),
+      name
+    ))
+  }
+
+  #[test]
+  fn records_and_replays() {
+    let path = recording_path("replay");
+
+    let browser = FakeBrowser::start();
+    browser.on("Test.answer", |cmd| {
+      Ok(serde_json::json!({ "answer": cmd.params["n"].as_u64().unwrap_or_default() * 2 }))
+    });
+
+    let options = ConnectionOptions {
+      recording: Some(path.clone()),
+      tracing: false,
+    };
+    let cdp = launch(browser.port(), &options).unwrap();
+
+    let events = cdp.subscribe("Test.event", None);
+    let closed = cdp.subscribe(CONNECTION_CLOSED_EVENT, None);
+
+    for n in [1, 2] {
+      let cmd = CDPCommand::new("Test.answer", serde_json::json!({ "n": n }), None);
+      cdp.send(cmd, None).unwrap();
+    }
+
+    browser.emit("Test.event", serde_json::json!({ "hello": "world" }), None);
+    events.recv_timeout(Duration::from_secs(1)).unwrap();
+    browser.disconnect();
+    closed.recv_timeout(Duration::from_secs(1)).unwrap();
+
+    let frames = load(&path).unwrap();
+    let directions = frames.iter().map(|f| f.direction).collect::<Vec<_>>();
+    assert_eq!(
+      directions,
+      [
+        Direction::Sent,
+        Direction::Received,
+        Direction::Sent,
+        Direction::Received,
+        Direction::Received,
+        Direction::Closed
+      ]
+    );
+
+    let cdp = Cdp::replay(&path).unwrap();
+    let events = cdp.subscribe("Test.event", None);
+    let closed = cdp.subscribe(CONNECTION_CLOSED_EVENT, None);
+
+    let cmd = CDPCommand::new("Test.unknown", Value::Null, None);
+    assert!(cdp.send(cmd, None).unwrap()["error"].is_object());
+
+    // Same commands, in the opposite order, still get their own responses. The connection closes after the last one, like it did.
+
+    for n in [2, 1] {
+      let cmd = CDPCommand::new("Test.answer", serde_json::json!({ "n": n }), None);
+      assert_eq!(cdp.send(cmd, None).unwrap()["result"]["answer"], n * 2);
+    }
+
+    assert_eq!(
+      events.recv_timeout(Duration::from_secs(1)).unwrap().params["hello"],
+      "world"
+    );
+    assert!(closed.recv_timeout(Duration::from_secs(1)).is_ok());
+
+    std::fs::remove_file(path).unwrap_or_default();
+  }
+
+  #[test]
+  fn plays_out_when_idle() {
+    let path = recording_path("idle");
+    let lines = [
+      r#"{"time":0,"direction":"sent","message":{"id":1,"method":"Test.never"}}"#,
+      r#"{"time":1,"direction":"received","message":{"id":1,"result":{}}}"#,
+      r#"{"time":2,"direction":"received","message":{"method":"Test.event","params":{}}}"#,
+    ];
+    std::fs::write(&path, lines.join("\n")).unwrap();
+
+    let cdp = Cdp::replay(&path).unwrap();
+    let events = cdp.subscribe("Test.event", None);
+
+    assert!(events.recv_timeout(Duration::from_secs(2)).is_ok());
+
+    std::fs::remove_file(path).unwrap_or_default();
+  }
+}
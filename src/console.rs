@@ -0,0 +1,163 @@
+use std::{
+  fmt::{Debug, Display},
+  sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Which `console` method a message was logged with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConsoleLevel {
+  Debug,
+  Log,
+  Info,
+  Warn,
+  Error,
+}
+
+/// A single call in a JavaScript stack trace. Lines and columns start at 1
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StackFrame {
+  /// Empty for anonymous functions and top-level code
+  pub function_name: String,
+  pub url: String,
+  pub line: u32,
+  pub column: u32,
+}
+
+impl StackFrame {
+  /// Build a frame from a CDP `CallFrame` or BiDi `StackFrame`, which share a shape and count from 0
+  pub(crate) fn from_protocol(frame: &Value) -> Self {
+    StackFrame {
+      function_name: frame["functionName"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string(),
+      url: frame["url"].as_str().unwrap_or_default().to_string(),
+      line: frame["lineNumber"].as_u64().unwrap_or_default() as u32 + 1,
+      column: frame["columnNumber"].as_u64().unwrap_or_default() as u32 + 1,
+    }
+  }
+
+  /// Frames from a CDP or BiDi `StackTrace`
+  pub(crate) fn from_stack_trace(stack_trace: &Value) -> Vec<Self> {
+    stack_trace["callFrames"]
+      .as_array()
+      .into_iter()
+      .flatten()
+      .map(StackFrame::from_protocol)
+      .collect()
+  }
+}
+
+/// Something the page logged with `console.log`, `console.error`, etc.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsoleMessage {
+  pub level: ConsoleLevel,
+  /// The arguments formatted together, roughly as the browser's devtools would show them
+  pub text: String,
+  /// The arguments as JSON. Values without a JSON equivalent (functions, DOM nodes, etc.) become `null` or a description
+  pub args: Vec<Value>,
+  /// Where the message was logged from, innermost call first
+  pub stack: Vec<StackFrame>,
+}
+
+/// An error thrown (or a promise rejected) by the page's JavaScript, either uncaught or from a script passed to `eval`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageException {
+  /// Usually the error's name and message, like `TypeError: x is undefined`
+  pub message: String,
+  /// The script the error was thrown in. Empty for scripts passed to `eval`
+  pub url: String,
+  /// Where the error was thrown, starting at 1. For scripts passed to `eval`, this is within the script
+  pub line: u32,
+  pub column: u32,
+  /// The calls leading up to the error, innermost first. Empty if the browser didn't say
+  pub stack: Vec<StackFrame>,
+}
+
+impl Display for PageException {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.url.is_empty() {
+      true => write!(
+        f,
+        "{} (line {}, column {})",
+        self.message, self.line, self.column
+      ),
+      false => write!(
+        f,
+        "{} ({}:{}:{})",
+        self.message, self.url, self.line, self.column
+      ),
+    }
+  }
+}
+
+pub type ConsoleCallback = Arc<dyn Fn(&ConsoleMessage) + Send + Sync + 'static>;
+pub type ExceptionCallback = Arc<dyn Fn(&PageException) + Send + Sync + 'static>;
+
+/// Where the page's console output and uncaught exceptions go
+#[derive(Default, Clone)]
+pub struct ConsoleHandlers {
+  pub(crate) on_message: Option<ConsoleCallback>,
+  pub(crate) on_exception: Option<ExceptionCallback>,
+  /// Also log everything with the `log` crate, under the `console` target
+  pub(crate) forward_to_log: bool,
+}
+
+impl Debug for ConsoleHandlers {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ConsoleHandlers")
+      .field("on_message", &self.on_message.is_some())
+      .field("on_exception", &self.on_exception.is_some())
+      .field("forward_to_log", &self.forward_to_log)
+      .finish()
+  }
+}
+
+impl ConsoleHandlers {
+  /// Whether anyone is listening at all
+  pub fn is_active(&self) -> bool {
+    self.on_message.is_some() || self.on_exception.is_some() || self.forward_to_log
+  }
+
+  pub fn message(&self, message: &ConsoleMessage) {
+    if self.forward_to_log {
+      let level = match message.level {
+        ConsoleLevel::Debug => log::Level::Debug,
+        ConsoleLevel::Log | ConsoleLevel::Info => log::Level::Info,
+        ConsoleLevel::Warn => log::Level::Warn,
+        ConsoleLevel::Error => log::Level::Error,
+      };
+
+      log::log!(target: "console", level, "{}{}", message.text, location(&message.stack));
+    }
+
+    if let Some(callback) = &self.on_message {
+      callback(message);
+    }
+  }
+
+  pub fn exception(&self, exception: &PageException) {
+    if self.forward_to_log {
+      log::error!(target: "console", "Uncaught {}", exception);
+    }
+
+    if let Some(callback) = &self.on_exception {
+      callback(exception);
+    }
+  }
+}
+
+/// ` (url:line:column)` for the innermost frame of a stack, if there is one
+fn location(stack: &[StackFrame]) -> String {
+  match stack.first() {
+    Some(frame) => format!(" ({}:{}:{})", frame.url, frame.line, frame.column),
+    None => String::new(),
+  }
+}
@@ -1,6 +1,6 @@
 use std::any::Any;
 
-use crate::webserver::WebserverMessage;
+use crate::{console::PageException, webserver::WebserverMessage};
 
 #[derive(Debug)]
 pub enum CrowserError {
@@ -15,8 +15,13 @@ pub enum CrowserError {
   DoBeforeCreate(String),
   WebserverError(String),
   CDPError(String),
+  BidiError(String),
+  /// A script passed to `eval` threw, or returned a promise that rejected
+  ScriptError(PageException),
+  /// The browser didn't answer in time. Whatever was asked of it may still finish later
+  Timeout(String),
   WebRequestError(minreq::Error),
-  WebsocketError(tungstenite::Error),
+  WebsocketError(Box<tungstenite::Error>),
   FromUtf8Error(std::string::FromUtf8Error),
   FlumeSendError(flume::SendError<String>),
   FlumeRecvError(flume::RecvError),
@@ -93,6 +98,9 @@ impl std::fmt::Display for CrowserError {
       CrowserError::DoBeforeCreate(msg) => write!(f, "Do before create error: {}", msg),
       CrowserError::WebserverError(msg) => write!(f, "Webserver error: {}", msg),
       CrowserError::CDPError(msg) => write!(f, "CDP error: {}", msg),
+      CrowserError::BidiError(msg) => write!(f, "WebDriver BiDi error: {}", msg),
+      CrowserError::ScriptError(err) => write!(f, "Script error: {}", err),
+      CrowserError::Timeout(msg) => write!(f, "Timed out: {}", msg),
       CrowserError::WebRequestError(err) => write!(f, "Web request error: {}", err),
       CrowserError::WebsocketError(err) => write!(f, "Websocket error: {}", err),
       CrowserError::FromUtf8Error(err) => write!(f, "UTF-8 error: {}", err),
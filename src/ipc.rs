@@ -1,32 +1,63 @@
 use std::{
   collections::HashMap,
   fmt::Debug,
-  sync::{Arc, Mutex},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
 };
 
 use serde_json::Value;
 
 use crate::{
-  cdp::{
-    self,
-    commands::{CDPCommand, RuntimeEvaluate, TargetAttachToTarget, TargetGetTargets, TargetSetDiscoverTargets},
-    Cdp,
-  },
+  backend::{self, Backend, BackendEvent, ConsoleEvent},
+  browser::BrowserKind,
+  cdp::{commands::CDPEvent, ConnectionOptions},
+  console::ConsoleHandlers,
   error::CrowserError,
-  util::javascript::IPC_JS,
+  navigation::{self, NavigationAction, NavigationPolicy},
+  popup::{PopupAction, PopupPolicy},
+  util::{self, javascript::IPC_JS},
+  ClipRect, PdfOptions, ScreenshotFormat, WindowBounds, WindowIpc,
 };
 
-type IpcRegistrationMap =
-  Arc<Mutex<HashMap<String, Vec<Box<dyn FnMut(Value) -> Result<Value, CrowserError> + Send + Sync + 'static>>>>>;
+/// How long `eval` and `call_function` wait for a script, unless told otherwise. Long enough for a slow promise,
+/// but not forever
+pub(crate) const DEFAULT_EVAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a frame to hand over its invokes. Kept short, since frames are polled one after the other
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long an iframe that didn't answer in time is left alone, so it can't keep slowing down every other frame
+const UNRESPONSIVE_BACKOFF: Duration = Duration::from_secs(1);
+
+type IpcRegistrationMap = Arc<
+  Mutex<
+    HashMap<
+      String,
+      Vec<Box<dyn FnMut(Value) -> Result<Value, CrowserError> + Send + Sync + 'static>>,
+    >,
+  >,
+>;
 
 #[derive(Clone)]
 pub struct BrowserIpc {
-  cdp: Arc<Mutex<Cdp>>,
-  session_id: String,
-  browser_session_id: String,
-  attached: bool,
+  backend: Arc<dyn Backend>,
+  /// Relative URLs are resolved against this, for local content served by our own webserver
+  base_url: Option<String>,
+  // Passed on to popups, so their console output goes to the same place
+  console: ConsoleHandlers,
+  /// URLs passed to `navigate`, which skip the navigation policy. `None` if there is no policy
+  expected_navigations: Option<Arc<Mutex<Vec<String>>>>,
+  /// Passed on to popups, so they can't go anywhere the window itself couldn't
+  navigation_policy: Option<NavigationPolicy>,
+  /// Set once the browser reports that the page is gone
+  closed: Arc<AtomicBool>,
 
   commands: IpcRegistrationMap,
+  /// Origins allowed to invoke each command. Commands that aren't in here can be invoked from anywhere
+  command_origins: Arc<Mutex<HashMap<String, Vec<String>>>>,
   listeners: IpcRegistrationMap,
 }
 
@@ -39,309 +70,432 @@ impl Debug for BrowserIpc {
     let l_keys = l_map.keys().collect::<Vec<&String>>();
 
     f.debug_struct("BrowserIpc")
-      .field("cdp", &self.cdp)
-      .field("session_id", &self.session_id)
-      .field("attached", &self.attached)
+      .field("backend", &self.backend)
       .field("commands", &c_keys)
+      .field("command_origins", &*self.command_origins.lock().unwrap())
       .field("listeners", &l_keys)
       .finish()
   }
 }
 
 impl BrowserIpc {
-  pub fn new(port: u16, initialization_script: impl AsRef<str>) -> Result<Self, CrowserError> {
-    let cdp = cdp::launch(port, initialization_script)?;
-    let mut ipc = BrowserIpc {
-      cdp: Arc::new(Mutex::new(cdp)),
-      session_id: String::new(),
-      browser_session_id: String::new(),
-      attached: false,
+  pub fn new(
+    port: u16,
+    initialization_scripts: Vec<String>,
+    kind: BrowserKind,
+    base_url: Option<String>,
+    options: &ConnectionOptions,
+    console: ConsoleHandlers,
+  ) -> Result<Self, CrowserError> {
+    let backend = backend::connect(kind, port, options)?;
+
+    Self::with_backend(backend, initialization_scripts, base_url, console, true)
+  }
 
-      commands: Arc::new(Mutex::new(HashMap::new())),
-      listeners: Arc::new(Mutex::new(HashMap::new())),
-    };
+  /// Set up IPC on a page that is already attached to, like a popup. Unless the page is still paused before loading
+  /// anything, it has already loaded by then, so the scripts either need a `reload`, or are run in the current document
+  /// as well. Popups shouldn't be reloaded, since that would resubmit forms and lose anything their opener wrote into them.
+  pub fn with_backend(
+    backend: Arc<dyn Backend>,
+    initialization_scripts: Vec<String>,
+    base_url: Option<String>,
+    console: ConsoleHandlers,
+    reload: bool,
+  ) -> Result<Self, CrowserError> {
+    let closed_rx = backend.subscribe(BackendEvent::Closed)?;
+    backend.add_preload_script(IPC_JS)?;
+
+    // Before the reload, so nothing the page logs while loading is missed
+    if console.is_active() {
+      let t_console = console.clone();
+
+      backend.watch_console(Arc::new(move |event| match event {
+        ConsoleEvent::Message(message) => t_console.message(&message),
+        ConsoleEvent::Exception(exception) => t_console.exception(&exception),
+      }))?;
+    }
 
-    ipc.attach()?;
+    for script in &initialization_scripts {
+      backend.add_preload_script(script)?;
+    }
 
-    let mut cb_ipc = ipc.clone();
+    if reload {
+      backend.reload(false)?;
+    } else if !backend.resume()? {
+      backend.evaluate(IPC_JS, None)?;
 
-    // Page.loadeventfired
-    ipc.cdp.lock().unwrap().on("Page.loadEventFired", move |cdp, _value| {
-      cb_ipc.eval(cdp.initialization_script.clone()).unwrap_or_default();
-      cb_ipc.inject();
-      Ok(Value::Null)
-    }).expect("Failed to register listener");
+      for script in &initialization_scripts {
+        if let Err(err) = backend.evaluate(script, None) {
+          log::warn!(target: "ipc", "Initialization script failed in the current document: {}", err);
+        }
+      }
+    }
 
-    // Kill the process if Target.targetDestroyed is fired
-    ipc.cdp.lock().unwrap().on("Target.targetDestroyed", move |_cdp, _value| {
-      std::process::exit(0);
-    }).expect("Failed to register listener");
+    let mut ipc = BrowserIpc {
+      backend,
+      base_url,
+      console,
+      expected_navigations: None,
+      navigation_policy: None,
+      closed: Arc::new(AtomicBool::new(false)),
 
-    // reload
-    let cmd = CDPCommand::new("Page.reload", serde_json::Value::Null, Some(ipc.session_id.clone()));
-    ipc.cdp.lock().unwrap().send(cmd, None)?;
+      commands: Arc::new(Mutex::new(HashMap::new())),
+      command_origins: Arc::new(Mutex::new(HashMap::new())),
+      listeners: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    // `Window::create` shuts the browser down once the window's page is closed, and the event loop stops. Losing the
+    // connection ends the subscription, and is just as final.
+    let closed = ipc.closed.clone();
+    std::thread::spawn(move || {
+      closed_rx.recv().unwrap_or_default();
+      closed.store(true, Ordering::Relaxed);
+    });
 
     ipc.event_loop()?;
 
     Ok(ipc)
   }
 
-  fn attach(&mut self) -> Result<(), CrowserError> {
-    let mut cdp = self.cdp.lock().unwrap();
+  /// Non-blocking event loop for handling IPC invokes from the page
+  fn event_loop(&mut self) -> Result<(), CrowserError> {
+    let mut ipc = self.clone();
+    let mut unresponsive = HashMap::<String, Instant>::new();
+
+    std::thread::spawn(move || {
+      while !ipc.is_closed() {
+        std::thread::sleep(Duration::from_millis(10));
+        unresponsive.retain(|_, until| Instant::now() < *until);
+
+        // The page and each of its iframes have their own invokes, and get what their own origin is allowed
+        for context in ipc.backend.execution_contexts() {
+          if unresponsive.contains_key(&context.id) {
+            continue;
+          }
 
-    // Get targets
-    let t_params = TargetGetTargets {};
-    let t_cmd = CDPCommand::new("Target.getTargets", t_params, None);
-    let result = cdp.send(t_cmd, None)?;
-    let result = result.get("result");
+          // Use eval to read from `_backend_consume()` on the JS side
+          let result = match ipc.backend.evaluate_in(
+            &context,
+            "window.__CROWSER.ipc._backend_consume()",
+            Some(POLL_TIMEOUT),
+          ) {
+            Ok(Some(val)) => val,
+            // The page itself is always polled, busy or not, since that's where the app's invokes come from
+            Err(CrowserError::Timeout(_)) if !context.top_level => {
+              log::debug!(target: "ipc", "Frame {} is unresponsive, skipping it for now", context.origin);
+              unresponsive.insert(context.id, Instant::now() + UNRESPONSIVE_BACKOFF);
+              continue;
+            }
+            _ => continue,
+          };
+
+          // This is an object with a 'cmd', an 'args' map, and a 'uuid' string
+          let (cmd, args, uuid) = match (
+            result["cmd"].as_str(),
+            result.get("args"),
+            result["uuid"].as_str(),
+          ) {
+            (Some(cmd), Some(args), Some(uuid)) => (cmd, args, uuid),
+            _ => continue,
+          };
+
+          // The invoke rejects with a `DOMException` of this name
+          let reject = |message: String, name: &str| {
+            format!(
+              "window.__CROWSER.ipc._backend_reject({}, {}, {})",
+              Value::from(uuid),
+              Value::from(message),
+              Value::from(name)
+            )
+          };
+
+          // Respond to the JS side. The UUID comes from the page, so it is escaped like any other value
+          let script = if !ipc.is_allowed(cmd, &context.origin) {
+            log::warn!(
+              target: "ipc",
+              "Rejected {} from {}, which is not allowed to invoke it",
+              cmd,
+              context.origin
+            );
+
+            reject(
+              format!("{} is not allowed from {}", cmd, context.origin),
+              "NotAllowedError",
+            )
+          } else if !ipc.commands.lock().unwrap().contains_key(cmd) {
+            log::debug!(target: "ipc", "The page invoked {}, which isn't registered", cmd);
+            reject(format!("Unknown command: {}", cmd), "NotFoundError")
+          } else {
+            match ipc.handle_command(cmd, args.clone(), uuid) {
+              Ok(response) => format!(
+                "window.__CROWSER.ipc._backend_respond({}, {})",
+                Value::from(uuid),
+                response
+              ),
+              Err(err) => {
+                log::debug!(target: "ipc", "Command {} failed: {}", cmd, err);
+                reject(err.to_string(), "OperationError")
+              }
+            }
+          };
 
-    let targets = match result {
-      Some(val) => val,
-      None => {
-        return Err(CrowserError::CDPError(
-          "Attach: No result found".to_string(),
-        ))
+          ipc
+            .backend
+            .evaluate_in(&context, &script, None)
+            .unwrap_or_default();
+        }
       }
-    };
+    });
 
-    let targets = match targets.get("targetInfos") {
-      Some(val) => val,
-      None => return Err(CrowserError::CDPError("No targets found".to_string())),
-    }
-    .as_array();
+    Ok(())
+  }
 
-    if let Some(targets) = targets {
-      for target in targets {
-        let t = target["type"].as_str().unwrap_or_default();
+  /// Send an arbitrary CDP command and return its `result`, or an error if the browser rejected it.
+  pub fn send_raw(
+    &self,
+    method: impl AsRef<str>,
+    params: Value,
+    browser: bool,
+  ) -> Result<Value, CrowserError> {
+    match self.backend.cdp() {
+      Some(cdp) => cdp.send_raw(method, params, browser),
+      None => Err(CrowserError::CDPError(
+        "Raw CDP commands are not available for this browser".to_string(),
+      )),
+    }
+  }
 
-        if t != "page" {
-          continue;
-        }
+  /// Subscribe to raw CDP events. See `Cdp::subscribe`.
+  pub fn subscribe(
+    &self,
+    method: impl AsRef<str>,
+    session_id: Option<String>,
+  ) -> Result<flume::Receiver<CDPEvent>, CrowserError> {
+    match self.backend.cdp() {
+      Some(cdp) => Ok(cdp.subscribe_raw(method, session_id)),
+      None => Err(CrowserError::CDPError(
+        "Raw CDP events are not available for this browser".to_string(),
+      )),
+    }
+  }
 
-        let t_params = TargetAttachToTarget {
-          target_id: target["targetId"].as_str().unwrap_or_default().to_string(),
-          flatten: true,
-        };
-        let t_cmd = CDPCommand::new("Target.attachToTarget", t_params, None);
-        cdp.send(t_cmd, None)?;
+  /// The session ID used for page-level commands, or for browser-level commands if `browser` is true.
+  pub fn session(&self, browser: bool) -> Option<String> {
+    self.backend.cdp().and_then(|cdp| cdp.session(browser))
+  }
 
-        // This triggers the Target.attachedToTarget event
-        let evt_result = cdp.wait_for_event("Target.attachedToTarget", None)?;
-        let evt_result = evt_result.params.get("sessionId");
+  /// Navigate the window to a new URL. URLs without a scheme are treated as paths within the local content, if there is any.
+  pub fn navigate(&self, url: impl AsRef<str>) -> Result<(), CrowserError> {
+    let url = self.resolve_url(url.as_ref());
 
-        if let Some(session_id) = evt_result {
-          self.session_id = session_id.as_str().unwrap_or_default().to_string();
-          break;
-        }
-      }
+    if let Some(expected) = &self.expected_navigations {
+      expected.lock().unwrap().push(url.clone());
     }
 
-    // Set discover targets
-    let cmd = CDPCommand::new("Target.setDiscoverTargets", TargetSetDiscoverTargets {
-      discover: true,
-    }, None);
-    cdp.send(cmd, None)?;
+    let result = self.backend.navigate(&url);
 
-    // Attach to the browser session
-    let cmd = CDPCommand::new(
-      "Target.attachToBrowserTarget",
-      serde_json::Value::Null,
-      None,
-    );
+    // A navigation that never started mustn't let a later one to the same URL through. One that timed out may still
+    // happen, so its entry stays
+    let started = matches!(result, Ok(_) | Err(CrowserError::Timeout(_)));
 
-    let b_result = cdp.send(cmd, None)?;
-    let b_result = b_result["result"]["params"].get("sessionId");
+    if let (false, Some(expected)) = (started, &self.expected_navigations) {
+      let mut expected = expected.lock().unwrap();
 
-    if let Some(b_result) = b_result {
-      self.browser_session_id = b_result.as_str().unwrap_or_default().to_string();
+      if let Some(index) = expected.iter().rposition(|e| *e == url) {
+        expected.remove(index);
+      }
     }
 
-    // Page enable
-    let cmd = CDPCommand::new(
-      "Page.enable",
-      serde_json::Value::Null,
-      Some(self.session_id.clone()),
-    );
-    cdp.send(cmd, None)?;
-
-    // Runtime enable
-    // This is partially a fix for Firefox
-    // lol: https://bugzilla.mozilla.org/show_bug.cgi?id=1623482#c12
-    let cmd = CDPCommand::new(
-      "Runtime.enable",
-      serde_json::Value::Null,
-      Some(self.session_id.clone()),
-    );
-    cdp.send(cmd, None)?;
+    result
+  }
 
-    self.attached = true;
+  /// Start enforcing a navigation policy on the page. Navigations made through `navigate` are always allowed.
+  pub fn set_navigation_policy(&mut self, policy: NavigationPolicy) -> Result<(), CrowserError> {
+    let expected = Arc::new(Mutex::new(Vec::<String>::new()));
+    self.expected_navigations = Some(expected.clone());
+    self.navigation_policy = Some(policy.clone());
+
+    self.backend.intercept_navigation(Box::new(move |url| {
+      let mut expected = expected.lock().unwrap();
+
+      // Browsers tend to normalize the URL a little, e.g. adding a trailing slash
+      if let Some(index) = expected
+        .iter()
+        .position(|e| e.trim_end_matches('/') == url.trim_end_matches('/'))
+      {
+        expected.remove(index);
+        return true;
+      }
 
-    Ok(())
+      match policy.decide(url) {
+        NavigationAction::Allow => true,
+        NavigationAction::Deny => {
+          log::debug!(target: "browser", "Denied navigation to {}", url);
+          false
+        }
+        NavigationAction::OpenExternal => {
+          log::debug!(target: "browser", "Opening {} externally", url);
+
+          if let Err(err) = util::open::open_external(url) {
+            log::warn!(target: "browser", "Could not open {}: {}", url, err);
+          }
+
+          false
+        }
+      }
+    }))
   }
 
-  fn inject(&mut self) {
-    self.eval(IPC_JS).unwrap_or_default();
+  pub fn reload(&self, ignore_cache: bool) -> Result<(), CrowserError> {
+    self.backend.reload(ignore_cache)
   }
 
-  /// Non-blocking event loop for tracking CDP events
-  fn event_loop(&mut self) -> Result<(), CrowserError> {
-    let root_ipc = Arc::new(Mutex::new(self.clone()));
+  pub fn go_back(&self) -> Result<(), CrowserError> {
+    self.backend.traverse_history(-1)
+  }
 
-    std::thread::spawn(move || {
-      let mut last_context_create_uid = String::new();
-
-      loop {
-        std::thread::sleep(std::time::Duration::from_millis(10));
-
-        let ipc = match root_ipc.try_lock() {
-          Ok(val) => val,
-          Err(_) => continue,
-        };
-        let mut cdp = match ipc.cdp.try_lock() {
-          Ok(val) => val,
-          Err(_) => continue,
-        };
-        // let listeners = match ipc.listeners.try_lock() {
-        //   Ok(val) => val,
-        //   Err(_) => continue,
-        // };
-
-        let refresh = cdp.last_event_by_name("Runtime.executionContextCreated");
-
-        // Drop these explicitly now to ensure the lock is available for the next event
-        drop(cdp);
-        drop(ipc);
-
-        if let Ok(Some(evt)) = refresh {
-          let uid = evt.params["context"]["uniqueId"]
-            .as_str()
-            .unwrap_or_default();
+  pub fn go_forward(&self) -> Result<(), CrowserError> {
+    self.backend.traverse_history(1)
+  }
 
-          if uid != last_context_create_uid {
-            last_context_create_uid = uid.to_string();
+  pub fn current_url(&self) -> Result<String, CrowserError> {
+    self.backend.current_url()
+  }
 
-            // We don't use try_lock here because we NEED to ensure IPC is reinjected right now
-            let mut ipc = root_ipc.lock().unwrap();
+  fn resolve_url(&self, url: &str) -> String {
+    // Anything before the first path, query or fragment separator with a colon in it is a scheme (http:, about:, data:, etc.)
+    let has_scheme = url
+      .split(['/', '?', '#'])
+      .next()
+      .is_some_and(|start| start.contains(':'));
 
-            // We need to reinject because a new context has been created
-            ipc.inject();
-          }
+    match &self.base_url {
+      Some(base) if !has_scheme => format!("{}{}", base, url.trim_start_matches('/')),
+      _ => url.to_string(),
+    }
+  }
+
+  /// Start handling popups opened by the page. Popups kept as windows get the same initialization scripts and popup policy,
+  /// and the same navigation policy if there is one, so call `set_navigation_policy` first.
+  pub fn set_popup_policy(
+    &self,
+    policy: PopupPolicy,
+    initialization_scripts: Vec<String>,
+  ) -> Result<(), CrowserError> {
+    let base_url = self.base_url.clone();
+    let console = self.console.clone();
+    let navigation_policy = self.navigation_policy.clone();
+    let t_policy = policy.clone();
+
+    self.backend.watch_popups(Box::new(move |popup| {
+      let policy = t_policy.clone();
+
+      match policy.decide(&popup.url) {
+        PopupAction::Deny => popup.backend.close().unwrap_or_default(),
+        PopupAction::OpenExternal => {
+          util::open::open_external(&popup.url).unwrap_or_default();
+          popup.backend.close().unwrap_or_default();
         }
+        PopupAction::OpenWindow => {
+          let mut ipc = match BrowserIpc::with_backend(
+            popup.backend.clone(),
+            initialization_scripts.clone(),
+            base_url.clone(),
+            console.clone(),
+            false,
+          ) {
+            Ok(ipc) => ipc,
+            Err(err) => {
+              log::warn!(target: "browser", "Could not set up the popup for {}: {}", popup.url, err);
+              popup.backend.close().unwrap_or_default();
+              return;
+            }
+          };
 
-        let ipc = match root_ipc.try_lock() {
-          Ok(val) => val,
-          Err(_) => continue,
-        };
-
-        // Now use eval to read from `_backend_consume()` on the JS side
-        let mut cdp = match ipc.cdp.try_lock() {
-          Ok(val) => val,
-          Err(_) => continue,
-        };
-
-        let cmd = CDPCommand::new(
-          "Runtime.evaluate",
-          RuntimeEvaluate {
-            expression: "window.__CROWSER.ipc._backend_consume()".to_string(),
-            await_promise: Some(true),
-            return_by_value: Some(true),
-          },
-          Some(ipc.session_id.clone()),
-        );
-        let result = match cdp.send(cmd, None) {
-          Ok(val) => val,
-          Err(_) => continue,
-        };
-        let result = result["result"]["result"].get("value");
-
-        drop(cdp);
-        drop(ipc);
-
-        // This is an object with a 'cmd', an 'args' map, and a 'uuid' string
-        if let Some(result) = result {
-          let result = result.as_object();
-
-          if let Some(result) = result {
-            let cmd = result.get("cmd");
-            let args = result.get("args");
-            let uuid = result.get("uuid");
-
-            // Requires lock here because we are mutating the IPC array, we will never get the command again otherwise!
-            if let (Some(cmd), Some(args), Some(uuid), Ok(mut ipc)) =
-              (cmd, args, uuid, root_ipc.lock())
-            {
-              let cmd = cmd.as_str().unwrap_or_default();
-              let uuid = uuid.as_str().unwrap_or_default();
-
-              // We don't care about the result, we just want to make sure the command is handled
-              ipc
-                .handle_command(cmd, args.clone(), uuid)
-                .unwrap_or_default();
-
-              let mut cdp = ipc.cdp.lock().unwrap();
-
-              // Send a response to the JS side
-              let cmd = CDPCommand::new(
-                "Runtime.evaluate",
-                RuntimeEvaluate {
-                  expression: format!(
-                    "window.__CROWSER.ipc._backend_respond('{}', {})",
-                    uuid, args
-                  ),
-                  await_promise: Some(true),
-                  return_by_value: Some(true),
-                },
-                Some(ipc.session_id.clone()),
-              );
-
-              cdp.send(cmd, None).unwrap_or_default();
+          // The popup's own site was just allowed by the popup policy, so it may keep navigating within it
+          if let Some(mut navigation_policy) = navigation_policy.clone() {
+            if let Some(origin) = navigation::origin(&popup.url) {
+              navigation_policy.allowed.push(origin);
+            }
+
+            if let Err(err) = ipc.set_navigation_policy(navigation_policy) {
+              log::warn!(target: "browser", "Could not restrict the popup's navigation: {}", err);
             }
           }
+
+          // Popups can open popups of their own
+          ipc
+            .set_popup_policy(policy.clone(), initialization_scripts.clone())
+            .unwrap_or_default();
+
+          if let Some(on_window) = &policy.on_window {
+            on_window(WindowIpc::new(Arc::new(Mutex::new(Some(ipc)))));
+          }
         }
       }
-    });
+    }))
+  }
 
-    Ok(())
+  pub fn screenshot(
+    &self,
+    format: ScreenshotFormat,
+    clip: Option<ClipRect>,
+    full_page: bool,
+  ) -> Result<Vec<u8>, CrowserError> {
+    self.backend.screenshot(format, clip, full_page)
   }
 
-  pub fn wait_until_attached(&mut self) -> Result<(), CrowserError> {
-    #[allow(clippy::while_immutable_condition)]
-    while !self.attached {
-      std::thread::sleep(std::time::Duration::from_millis(10));
-    }
+  pub fn print_to_pdf(&self, options: PdfOptions) -> Result<Vec<u8>, CrowserError> {
+    self.backend.print_to_pdf(options)
+  }
+
+  /// Open the print dialog. `window.print()` blocks the page until the dialog closes, so it is called asynchronously
+  pub fn print(&self) -> Result<(), CrowserError> {
+    self
+      .backend
+      .evaluate("setTimeout(() => window.print(), 0)", None)?;
 
     Ok(())
   }
 
-  pub fn eval(&mut self, script: impl AsRef<str>) -> Result<Value, CrowserError> {
-    self.wait_until_attached()?;
+  /// Get the position, size and state of the OS window
+  pub fn window_bounds(&self) -> Result<WindowBounds, CrowserError> {
+    self.backend.window_bounds()
+  }
 
-    let mut cdp = self.cdp.lock().unwrap();
-    let params = RuntimeEvaluate {
-      expression: script.as_ref().to_string(),
-      await_promise: Some(true),
-      return_by_value: Some(true),
-    };
+  /// Move, resize, or change the state of the OS window
+  pub fn set_window_bounds(&self, bounds: WindowBounds) -> Result<(), CrowserError> {
+    self.backend.set_window_bounds(bounds)
+  }
 
-    let cmd = CDPCommand::new("Runtime.evaluate", params, Some(self.session_id.clone()));
-    println!("Before send: {:?}", cmd);
-    let result = cdp.send(cmd, None)?;
-    println!("After send: {:?}", result["result"]["result"]);
-    let res_type = result["result"]["result"]["type"]
-      .as_str()
-      .unwrap_or_default();
+  /// Size the page itself, rather than the window around it
+  pub fn set_viewport(&self, width: u32, height: u32) -> Result<(), CrowserError> {
+    self.backend.set_viewport(width, height)
+  }
 
-    if ["string", "number", "boolean", "bigint", "symbol"].contains(&res_type) {
-      return match result["result"]["result"].get("value") {
-        Some(val) => Ok(val.clone()),
-        None => Err(CrowserError::CDPError(format!(
-          "Eval: No result found in object: {:?}",
-          result
-        ))),
-      };
-    }
+  /// Ask the browser to close the window
+  pub fn close(&self) -> Result<(), CrowserError> {
+    self.backend.close()
+  }
+
+  /// Eval a script, giving up with `CrowserError::Timeout` if it hasn't finished within `timeout`
+  pub fn eval(
+    &self,
+    script: impl AsRef<str>,
+    timeout: Duration,
+  ) -> Result<Option<Value>, CrowserError> {
+    self.backend.evaluate(script.as_ref(), Some(timeout))
+  }
 
-    Ok(Value::Null)
+  pub fn call_function(
+    &self,
+    function: impl AsRef<str>,
+    args: Vec<Value>,
+    timeout: Duration,
+  ) -> Result<Option<Value>, CrowserError> {
+    self
+      .backend
+      .call_function(function.as_ref(), args, Some(timeout))
   }
 
   pub fn register_command(
@@ -362,6 +516,49 @@ impl BrowserIpc {
     Ok(())
   }
 
+  /// Register a command that only frames from the given origins may invoke. Origins are like `https://example.com`, and
+  /// may contain `*` wildcards (`https://*.example.com`). `self` is the origin of the window's local content.
+  pub fn register_command_with_origins(
+    &mut self,
+    name: impl AsRef<str>,
+    origins: Vec<String>,
+    callback: impl FnMut(Value) -> Result<Value, CrowserError> + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    // Both are held throughout, so the command can't be invoked before it's restricted. Always `commands` first,
+    // like everywhere else that takes both
+    let mut commands = self.commands.lock().unwrap();
+    let mut command_origins = self.command_origins.lock().unwrap();
+
+    if commands.contains_key(name.as_ref()) {
+      return Err(CrowserError::IpcError("Command already exists".to_string()));
+    }
+
+    command_origins.insert(name.as_ref().to_string(), origins);
+    commands.insert(name.as_ref().to_string(), vec![Box::new(callback)]);
+
+    Ok(())
+  }
+
+  /// Whether a frame from `origin` may invoke a command
+  pub fn is_allowed(&self, cmd: impl AsRef<str>, origin: &str) -> bool {
+    let command_origins = self.command_origins.lock().unwrap();
+    let allowed = match command_origins.get(cmd.as_ref()) {
+      Some(allowed) => allowed,
+      None => return true,
+    };
+
+    let own_origin = self
+      .base_url
+      .as_deref()
+      .and_then(navigation::origin)
+      .unwrap_or_default();
+
+    allowed.iter().any(|pattern| match pattern.as_str() {
+      "self" => !own_origin.is_empty() && own_origin == origin,
+      pattern => navigation::origin_matches(pattern, origin),
+    })
+  }
+
   pub fn listen(
     &mut self,
     name: impl AsRef<str>,
@@ -388,21 +585,736 @@ impl BrowserIpc {
     Ok(())
   }
 
+  /// Run a command and return its result
   pub fn handle_command(
     &mut self,
     cmd: impl AsRef<str>,
     args: Value,
     _uuid: impl AsRef<str>,
-  ) -> Result<(), CrowserError> {
+  ) -> Result<Value, CrowserError> {
     let mut commands = self.commands.lock().unwrap();
     let cmd = cmd.as_ref();
 
-    if let Some(callbacks) = commands.get_mut(cmd) {
-      for callback in callbacks.iter_mut() {
-        callback(args.clone()).unwrap_or_default();
+    log::debug!(target: "ipc", "Invoked {}", cmd);
+
+    // Commands can only be registered once, so there is only ever one callback to get a result from
+    let result = match commands.get_mut(cmd).and_then(|c| c.first_mut()) {
+      Some(callback) => callback(args),
+      None => Err(CrowserError::IpcError(format!("Unknown command: {}", cmd))),
+    };
+
+    if let Err(err) = &result {
+      log::warn!(target: "ipc", "Command {} failed: {}", cmd, err);
+    }
+
+    result
+  }
+
+  /// Whether the browser has reported that the page was closed, or the connection to it was lost
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Relaxed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::{
+    console::{ConsoleLevel, ConsoleMessage, PageException},
+    test_support::{
+      FakeBrowser, BROWSER_SESSION_ID, PAGE_CONTEXT_ID, PAGE_SESSION_ID, PAGE_TARGET_ID,
+    },
+  };
+
+  const TIMEOUT: Duration = Duration::from_secs(2);
+
+  fn connect(browser: &FakeBrowser) -> BrowserIpc {
+    BrowserIpc::new(
+      browser.port(),
+      vec!["window.initialized = true".to_string()],
+      BrowserKind::Chromium,
+      None,
+      &ConnectionOptions::default(),
+      ConsoleHandlers::default(),
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn attaches_and_installs_scripts() {
+    let browser = FakeBrowser::start();
+    let _ipc = connect(&browser);
+
+    let attach = browser.wait_for("Target.attachToTarget", TIMEOUT).unwrap();
+    assert_eq!(attach.params["targetId"], PAGE_TARGET_ID);
+
+    // IPC first, then the app's own scripts, both on the page's session
+    let scripts = browser
+      .received()
+      .into_iter()
+      .filter(|cmd| cmd.method == "Page.addScriptToEvaluateOnNewDocument")
+      .collect::<Vec<_>>();
+    assert_eq!(scripts.len(), 2);
+    assert_eq!(scripts[0].params["source"], IPC_JS);
+    assert_eq!(scripts[1].params["source"], "window.initialized = true");
+    assert_eq!(scripts[1].session_id.as_deref(), Some(PAGE_SESSION_ID));
+
+    assert!(browser.wait_for("Page.reload", TIMEOUT).is_some());
+  }
+
+  #[test]
+  fn popups_run_scripts_without_reloading() {
+    let browser = FakeBrowser::start();
+    let backend = backend::connect(
+      BrowserKind::Chromium,
+      browser.port(),
+      &ConnectionOptions::default(),
+    )
+    .unwrap();
+    let _ipc = BrowserIpc::with_backend(
+      backend,
+      vec!["window.initialized = true".to_string()],
+      None,
+      ConsoleHandlers::default(),
+      false,
+    )
+    .unwrap();
+
+    // Installed for new documents, and run in the current one
+    let received = browser.received();
+    let sources = |method: &str, key: &str| {
+      received
+        .iter()
+        .filter(|cmd| cmd.method == method)
+        .map(|cmd| cmd.params[key].as_str().unwrap_or_default().to_string())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      sources("Page.addScriptToEvaluateOnNewDocument", "source"),
+      [IPC_JS, "window.initialized = true"]
+    );
+    assert_eq!(
+      sources("Runtime.evaluate", "expression")[..2],
+      [IPC_JS, "window.initialized = true"]
+    );
+    assert!(received.iter().all(|cmd| cmd.method != "Page.reload"));
+  }
+
+  #[test]
+  fn eval_returns_value() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+
+    assert_eq!(
+      ipc.eval("{\"a\": [1, 2]}", TIMEOUT).unwrap(),
+      Some(serde_json::json!({ "a": [1, 2] }))
+    );
+  }
+
+  #[test]
+  fn eval_reports_undefined_and_exceptions() {
+    let browser = FakeBrowser::start();
+    browser.on("Runtime.evaluate", |cmd| {
+      Ok(match cmd.params["expression"].as_str().unwrap_or_default() {
+        "fail()" => serde_json::json!({
+          "result": { "type": "object", "subtype": "error", "description": "Error: Nope\n    at fail (<anonymous>:1:7)" },
+          "exceptionDetails": {
+            "text": "Uncaught",
+            "lineNumber": 0,
+            "columnNumber": 6,
+            "exception": { "type": "object", "subtype": "error", "description": "Error: Nope\n    at fail (<anonymous>:1:7)" },
+            "stackTrace": {
+              "callFrames": [{ "functionName": "fail", "url": "", "lineNumber": 0, "columnNumber": 6 }],
+            },
+          },
+        }),
+        "Promise.reject('Also nope')" => serde_json::json!({
+          "result": { "type": "string", "value": "Also nope" },
+          "exceptionDetails": {
+            "text": "Uncaught (in promise)",
+            "lineNumber": 0,
+            "columnNumber": 0,
+            "exception": { "type": "string", "value": "Also nope" },
+          },
+        }),
+        "null" => serde_json::json!({ "result": { "type": "object", "subtype": "null", "value": null } }),
+        _ => serde_json::json!({ "result": { "type": "undefined" } }),
+      })
+    });
+
+    let ipc = connect(&browser);
+
+    assert_eq!(ipc.eval("null", TIMEOUT).unwrap(), Some(Value::Null));
+    assert_eq!(ipc.eval("undefined", TIMEOUT).unwrap(), None);
+
+    match ipc.eval("fail()", TIMEOUT) {
+      Err(CrowserError::ScriptError(exception)) => {
+        assert_eq!(exception.message, "Error: Nope");
+        assert_eq!((exception.line, exception.column), (1, 7));
+        assert_eq!(exception.stack[0].function_name, "fail");
       }
+      other => panic!("Expected a script error, got {:?}", other),
     }
 
-    Ok(())
+    match ipc.eval("Promise.reject('Also nope')", TIMEOUT) {
+      Err(CrowserError::ScriptError(exception)) => assert_eq!(exception.message, "Also nope"),
+      other => panic!("Expected a script error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn eval_times_out() {
+    let browser = FakeBrowser::start();
+    browser.on("Runtime.evaluate", |cmd| {
+      if cmd.params["expression"] == "new Promise(() => {})" {
+        std::thread::sleep(Duration::from_millis(500));
+      }
+
+      Ok(serde_json::json!({ "result": { "type": "undefined" } }))
+    });
+
+    let ipc = connect(&browser);
+
+    assert!(matches!(
+      ipc.eval("new Promise(() => {})", Duration::from_millis(50)),
+      Err(CrowserError::Timeout(_))
+    ));
+  }
+
+  #[test]
+  fn call_function_passes_arguments() {
+    let browser = FakeBrowser::start();
+    browser.on("Runtime.callFunctionOn", |_| {
+      Ok(serde_json::json!({ "result": { "type": "string", "value": "Hello, Crowser!" } }))
+    });
+
+    let ipc = connect(&browser);
+    let untrusted = "\"); alert(\"oops";
+    let result = ipc
+      .call_function(
+        "(greeting, name) => `${greeting}, ${name}!`",
+        vec![Value::from("Hello"), Value::from(untrusted)],
+        TIMEOUT,
+      )
+      .unwrap();
+
+    assert_eq!(result, Some(Value::from("Hello, Crowser!")));
+
+    // A single round trip, in the page's own context
+    let call = browser.wait_for("Runtime.callFunctionOn", TIMEOUT).unwrap();
+    assert_eq!(call.params["executionContextId"], PAGE_CONTEXT_ID);
+    assert_eq!(
+      call.params["arguments"],
+      serde_json::json!([{ "value": "Hello" }, { "value": untrusted }])
+    );
+    assert!(browser.received().iter().all(|cmd| {
+      cmd.method != "Runtime.releaseObject" && cmd.params["expression"] != "globalThis"
+    }));
+  }
+
+  #[test]
+  fn invoke_round_trip() {
+    let browser = FakeBrowser::start();
+
+    // The page has a single invoke waiting to be picked up
+    let mut pending =
+      Some(serde_json::json!({ "cmd": "greet", "args": { "name": "Crowser" }, "uuid": "1234" }));
+    browser.on("Runtime.evaluate", move |cmd| {
+      let value = match cmd.params["expression"].as_str() {
+        Some("window.__CROWSER.ipc._backend_consume()") => pending.take().unwrap_or(Value::Null),
+        _ => Value::Null,
+      };
+
+      Ok(serde_json::json!({ "result": { "type": "object", "value": value } }))
+    });
+
+    let mut ipc = connect(&browser);
+    ipc
+      .register_command("greet", |args| {
+        Ok(Value::from(format!(
+          "Hello, {}!",
+          args["name"].as_str().unwrap_or_default()
+        )))
+      })
+      .unwrap();
+
+    let response = browser.wait_for_matching(
+      |cmd| {
+        cmd.params["expression"]
+          .as_str()
+          .is_some_and(|e| e.starts_with("window.__CROWSER.ipc._backend_respond"))
+      },
+      TIMEOUT,
+    );
+
+    assert_eq!(
+      response.unwrap().params["expression"],
+      "window.__CROWSER.ipc._backend_respond(\"1234\", \"Hello, Crowser!\")"
+    );
+  }
+
+  #[test]
+  fn failed_and_unknown_commands_reject() {
+    let browser = FakeBrowser::start();
+
+    let mut pending = vec![
+      serde_json::json!({ "cmd": "missing", "args": {}, "uuid": "2" }),
+      serde_json::json!({ "cmd": "fail", "args": {}, "uuid": "1" }),
+    ];
+    browser.on("Runtime.evaluate", move |cmd| {
+      let value = match cmd.params["expression"].as_str() {
+        Some("window.__CROWSER.ipc._backend_consume()") => pending.pop().unwrap_or(Value::Null),
+        _ => Value::Null,
+      };
+
+      Ok(serde_json::json!({ "result": { "type": "object", "value": value } }))
+    });
+
+    let mut ipc = connect(&browser);
+    ipc
+      .register_command("fail", |_| {
+        Err(CrowserError::IpcError("Disk is full".to_string()))
+      })
+      .unwrap();
+
+    let rejection = |uuid: &str| {
+      let prefix = format!("window.__CROWSER.ipc._backend_reject(\"{}\"", uuid);
+      browser
+        .wait_for_matching(
+          |cmd| {
+            cmd.params["expression"]
+              .as_str()
+              .is_some_and(|e| e.starts_with(&prefix))
+          },
+          TIMEOUT,
+        )
+        .unwrap()
+        .params["expression"]
+        .clone()
+    };
+
+    assert_eq!(
+      rejection("1"),
+      format!(
+        "window.__CROWSER.ipc._backend_reject(\"1\", {}, \"OperationError\")",
+        Value::from(CrowserError::IpcError("Disk is full".to_string()).to_string())
+      )
+    );
+    assert_eq!(
+      rejection("2"),
+      "window.__CROWSER.ipc._backend_reject(\"2\", \"Unknown command: missing\", \"NotFoundError\")"
+    );
+  }
+
+  #[test]
+  fn rejects_commands_from_disallowed_origins() {
+    let browser = FakeBrowser::start();
+
+    // Only the iframe has an invoke waiting
+    let mut pending = Some(serde_json::json!({ "cmd": "secret", "args": {}, "uuid": "5678" }));
+    browser.on("Runtime.evaluate", move |cmd| {
+      let value = match (
+        cmd.params["expression"].as_str(),
+        cmd.params["contextId"].as_i64(),
+      ) {
+        (Some("window.__CROWSER.ipc._backend_consume()"), Some(2)) => {
+          pending.take().unwrap_or(Value::Null)
+        }
+        _ => Value::Null,
+      };
+
+      Ok(serde_json::json!({ "result": { "type": "object", "value": value } }))
+    });
+
+    let mut ipc = connect(&browser);
+    let invoked = Arc::new(AtomicBool::new(false));
+    let t_invoked = invoked.clone();
+    ipc
+      .register_command_with_origins(
+        "secret",
+        vec!["https://*.app.example".to_string()],
+        move |_| {
+          t_invoked.store(true, Ordering::SeqCst);
+          Ok(Value::Null)
+        },
+      )
+      .unwrap();
+
+    assert!(ipc.is_allowed("secret", "https://www.app.example"));
+    assert!(!ipc.is_allowed("secret", "https://ads.example"));
+    assert!(ipc.is_allowed("anything else", "https://ads.example"));
+
+    browser.emit(
+      "Runtime.executionContextCreated",
+      serde_json::json!({
+        "context": {
+          "id": 2,
+          "origin": "https://ads.example",
+          "name": "",
+          "auxData": { "isDefault": true, "type": "default", "frameId": "fake-frame" },
+        },
+      }),
+      Some(PAGE_SESSION_ID),
+    );
+
+    let response = browser
+      .wait_for_matching(
+        |cmd| {
+          cmd.params["expression"]
+            .as_str()
+            .is_some_and(|e| e.starts_with("window.__CROWSER.ipc._backend_re"))
+            && cmd.params["contextId"] == 2
+        },
+        TIMEOUT,
+      )
+      .unwrap();
+
+    assert_eq!(
+      response.params["expression"],
+      "window.__CROWSER.ipc._backend_reject(\"5678\", \"secret is not allowed from https://ads.example\", \"NotAllowedError\")"
+    );
+    assert!(!invoked.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn unresponsive_iframes_are_polled_less() {
+    let browser = FakeBrowser::start();
+
+    // The iframe takes longer to answer than polls are willing to wait
+    browser.on("Runtime.evaluate", |cmd| {
+      if cmd.params["contextId"] == 2 {
+        std::thread::sleep(POLL_TIMEOUT + Duration::from_millis(50));
+      }
+
+      Ok(serde_json::json!({ "result": { "type": "object", "value": null } }))
+    });
+
+    let _ipc = connect(&browser);
+    browser.emit(
+      "Runtime.executionContextCreated",
+      serde_json::json!({
+        "context": {
+          "id": 2,
+          "origin": "https://slow.example",
+          "name": "",
+          "auxData": { "isDefault": true, "type": "default", "frameId": "fake-frame" },
+        },
+      }),
+      Some(PAGE_SESSION_ID),
+    );
+
+    std::thread::sleep(UNRESPONSIVE_BACKOFF / 2);
+
+    let polls = browser
+      .received()
+      .into_iter()
+      .filter(|cmd| cmd.method == "Runtime.evaluate" && cmd.params["contextId"] == 2)
+      .count();
+    assert_eq!(polls, 1);
+  }
+
+  #[test]
+  fn navigate_waits_for_slow_servers() {
+    let browser = FakeBrowser::start();
+    browser.on("Page.navigate", |_| {
+      std::thread::sleep(Duration::from_millis(1500));
+      Ok(serde_json::json!({ "frameId": PAGE_TARGET_ID }))
+    });
+
+    let ipc = connect(&browser);
+    ipc.navigate("https://slow.example/").unwrap();
+  }
+
+  #[test]
+  fn unknown_command_is_an_error() {
+    let browser = FakeBrowser::start();
+    let mut ipc = connect(&browser);
+
+    assert!(ipc.handle_command("nope", Value::Null, "").is_err());
+  }
+
+  #[test]
+  fn forwards_console_output() {
+    let browser = FakeBrowser::start();
+    let (messages_tx, messages) = flume::unbounded();
+    let (exceptions_tx, exceptions) = flume::unbounded();
+
+    let console = ConsoleHandlers {
+      on_message: Some(Arc::new(move |message: &ConsoleMessage| {
+        messages_tx.send(message.clone()).unwrap_or_default();
+      })),
+      on_exception: Some(Arc::new(move |exception: &PageException| {
+        exceptions_tx.send(exception.clone()).unwrap_or_default();
+      })),
+      forward_to_log: false,
+    };
+
+    let _ipc = BrowserIpc::new(
+      browser.port(),
+      vec![],
+      BrowserKind::Chromium,
+      None,
+      &ConnectionOptions::default(),
+      console,
+    )
+    .unwrap();
+
+    browser.emit(
+      "Runtime.consoleAPICalled",
+      serde_json::json!({
+        "type": "warning",
+        "args": [
+          { "type": "string", "value": "answer:" },
+          {
+            "type": "object",
+            "preview": {
+              "type": "object",
+              "properties": [{ "name": "value", "type": "number", "value": "42" }],
+            },
+          },
+          { "type": "undefined" },
+        ],
+        "stackTrace": {
+          "callFrames": [{ "functionName": "main", "url": "app.js", "lineNumber": 9, "columnNumber": 4 }],
+        },
+      }),
+      Some(PAGE_SESSION_ID),
+    );
+
+    let message = messages.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(message.level, ConsoleLevel::Warn);
+    assert_eq!(message.text, "answer: {\"value\":42} undefined");
+    assert_eq!(
+      message.args,
+      [
+        Value::from("answer:"),
+        serde_json::json!({ "value": 42 }),
+        Value::Null
+      ]
+    );
+    assert_eq!(message.stack[0].line, 10);
+
+    browser.emit(
+      "Runtime.exceptionThrown",
+      serde_json::json!({
+        "exceptionDetails": {
+          "text": "Uncaught",
+          "url": "app.js",
+          "lineNumber": 0,
+          "columnNumber": 6,
+          "exception": { "type": "object", "description": "TypeError: x is undefined\n    at app.js:1:7" },
+        },
+      }),
+      Some(PAGE_SESSION_ID),
+    );
+
+    let exception = exceptions.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(exception.message, "TypeError: x is undefined");
+    assert_eq!(exception.url, "app.js");
+    assert_eq!((exception.line, exception.column), (1, 7));
+  }
+
+  #[test]
+  fn close_marks_closed() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+
+    assert!(!ipc.is_closed());
+    ipc.close().unwrap();
+
+    let close = browser.wait_for("Target.closeTarget", TIMEOUT).unwrap();
+    assert_eq!(close.params["targetId"], PAGE_TARGET_ID);
+
+    let start = std::time::Instant::now();
+    while !ipc.is_closed() && start.elapsed() < TIMEOUT {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(ipc.is_closed());
+  }
+
+  #[test]
+  fn losing_the_connection_marks_closed() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+
+    browser.disconnect();
+
+    let start = std::time::Instant::now();
+    while !ipc.is_closed() && start.elapsed() < TIMEOUT {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(ipc.is_closed());
+  }
+
+  #[test]
+  fn closed_popups_stop_polling() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+    open_popup(
+      &browser,
+      &ipc,
+      PopupAction::OpenWindow,
+      Some(PAGE_TARGET_ID),
+    );
+    assert!(resumed(&browser, TIMEOUT));
+
+    browser.emit(
+      "Runtime.executionContextCreated",
+      serde_json::json!({
+        "context": {
+          "id": 1,
+          "origin": "https://popup.example",
+          "auxData": { "isDefault": true, "frameId": "popup" },
+        },
+      }),
+      Some("popup-session"),
+    );
+
+    let polls = || {
+      browser
+        .received()
+        .iter()
+        .filter(|cmd| {
+          cmd.method == "Runtime.evaluate" && cmd.session_id.as_deref() == Some("popup-session")
+        })
+        .count()
+    };
+    let start = std::time::Instant::now();
+    while polls() == 0 && start.elapsed() < TIMEOUT {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(polls() > 0);
+
+    browser.emit(
+      "Target.targetDestroyed",
+      serde_json::json!({ "targetId": "popup" }),
+      None,
+    );
+
+    // A poll may have been in flight, but nothing after that
+    std::thread::sleep(Duration::from_millis(200));
+    let after_close = polls();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(polls(), after_close);
+  }
+
+  /// Opens a paused popup of the page (or some other page, without `opener`) once the popup policy is being enforced
+  fn open_popup(
+    browser: &FakeBrowser,
+    ipc: &BrowserIpc,
+    action: PopupAction,
+    opener: Option<&str>,
+  ) {
+    let policy = PopupPolicy {
+      callback: Some(Arc::new(move |_| action)),
+      on_window: None,
+    };
+    ipc.set_popup_policy(policy, vec![]).unwrap();
+
+    browser
+      .wait_for_matching(
+        |cmd| {
+          cmd.method == "Target.setAutoAttach"
+            && cmd.session_id.as_deref() == Some(BROWSER_SESSION_ID)
+        },
+        TIMEOUT,
+      )
+      .unwrap();
+
+    browser.emit(
+      "Target.attachedToTarget",
+      serde_json::json!({
+        "sessionId": "popup-session",
+        "targetInfo": {
+          "targetId": "popup",
+          "type": "page",
+          "url": "https://popup.example/",
+          "openerId": opener,
+        },
+        "waitingForDebugger": true,
+      }),
+      Some(BROWSER_SESSION_ID),
+    );
+  }
+
+  fn resumed(browser: &FakeBrowser, timeout: Duration) -> bool {
+    browser
+      .wait_for_matching(
+        |cmd| {
+          cmd.method == "Runtime.runIfWaitingForDebugger"
+            && cmd.session_id.as_deref() == Some("popup-session")
+        },
+        timeout,
+      )
+      .is_some()
+  }
+
+  #[test]
+  fn denied_popups_close_without_running() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+    open_popup(&browser, &ipc, PopupAction::Deny, Some(PAGE_TARGET_ID));
+
+    let close = browser
+      .wait_for_matching(
+        |cmd| cmd.method == "Target.closeTarget" && cmd.params["targetId"] == "popup",
+        TIMEOUT,
+      )
+      .unwrap();
+    assert_eq!(close.method, "Target.closeTarget");
+    assert!(!resumed(&browser, Duration::from_millis(200)));
+  }
+
+  #[test]
+  fn allowed_popups_resume_after_their_scripts() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+    open_popup(
+      &browser,
+      &ipc,
+      PopupAction::OpenWindow,
+      Some(PAGE_TARGET_ID),
+    );
+
+    assert!(resumed(&browser, TIMEOUT));
+
+    // Installed before the popup's first document, so there's nothing to run them in yet
+    let popup = browser
+      .received()
+      .into_iter()
+      .filter(|cmd| cmd.session_id.as_deref() == Some("popup-session"))
+      .collect::<Vec<_>>();
+    let resume = popup
+      .iter()
+      .position(|cmd| cmd.method == "Runtime.runIfWaitingForDebugger")
+      .unwrap();
+    assert!(popup[..resume]
+      .iter()
+      .any(|cmd| cmd.method == "Page.addScriptToEvaluateOnNewDocument"
+        && cmd.params["source"] == IPC_JS));
+    assert!(popup.iter().all(|cmd| cmd.method != "Runtime.evaluate"));
+  }
+
+  #[test]
+  fn other_pages_are_let_go() {
+    let browser = FakeBrowser::start();
+    let ipc = connect(&browser);
+    open_popup(&browser, &ipc, PopupAction::Deny, None);
+
+    assert!(resumed(&browser, TIMEOUT));
+    assert!(browser
+      .wait_for_matching(
+        |cmd| cmd.method == "Target.detachFromTarget" && cmd.params["sessionId"] == "popup-session",
+        TIMEOUT,
+      )
+      .is_some());
+    assert!(browser
+      .received()
+      .iter()
+      .all(|cmd| cmd.method != "Target.closeTarget"));
   }
 }
@@ -30,7 +30,7 @@ All browser support comes with a few small caveats. You may notice small inconsi
 More examples can be found in the [examples](./examples) directory. Try them with `cargo run --example <example>`!
 
 ## Displaying a remote website
-```rust
+```rust,ignore
 use crowser::{error::CrowserError, RemoteConfig, Window};
 
 fn main() -> Result<(), CrowserError> {
@@ -54,7 +54,7 @@ fn main() -> Result<(), CrowserError> {
 ```
 
 ## Embedding a local website
-```rust
+```rust,ignore
 use crowser::{error::CrowserError, include_dir, LocalConfig, Window};
 
 fn main() -> Result<(), CrowserError> {
@@ -78,7 +78,7 @@ fn main() -> Result<(), CrowserError> {
 ```
 
 ## Registering a command
-```rust
+```rust,ignore
 use crowser::{error::CrowserError, ipc::BrowserIpc, RemoteConfig, Window};
 
 fn main() -> Result<(), CrowserError> {
@@ -126,7 +126,7 @@ On a high level, Crowser works by first detecting browser installations on the u
 profile directory, or both. For example, for Firefox there is a `user.js` file in all profiles that can control much of the browser's default behavior. In Chromium-based browsers, there are a stupid amount of command-line arguments that can be
 used to control the browser's behavior ([check out this huge list!](https://peter.sh/experiments/chromium-command-line-switches/)).
 
-IPC is facilitated through the [Chrome DevTools Protocol](https://chromedevtools.github.io/devtools-protocol/). To keep the binary size small, the implementation is custom and therefore a little scuffed, but developers do not have to
+IPC is facilitated through the [Chrome DevTools Protocol](https://chromedevtools.github.io/devtools-protocol/), or [WebDriver BiDi](https://w3c.github.io/webdriver-bidi/) for Firefox-based browsers. To keep the binary size small, the implementation is custom and therefore a little scuffed, but developers do not have to
 care about it anyways!
 */
 
@@ -136,18 +136,34 @@ use std::{
 };
 
 use browser::{get_browser_path, Browser, BrowserKind};
+use cdp::ConnectionOptions;
+use console::ConsoleHandlers;
 use error::CrowserError;
 use include_dir::Dir;
+use navigation::NavigationPolicy;
+use popup::PopupPolicy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 use shared_child::SharedChild;
 
+mod backend;
+mod bidi;
 pub mod browser;
 mod cdp;
+mod console;
 pub mod error;
 mod ipc;
+mod navigation;
+mod popup;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 mod util;
 mod webserver;
 
+pub use cdp::commands::CDPEvent;
+pub use console::{ConsoleLevel, ConsoleMessage, PageException, StackFrame};
+pub use navigation::NavigationAction;
+pub use popup::PopupAction;
 // Re-export the include_dir macro
 pub use include_dir;
 use util::port::get_available_port;
@@ -167,6 +183,82 @@ pub struct ChromiumConfig {
   pub extensions: Vec<PathBuf>,
 }
 
+/// The state of a browser window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WindowState {
+  #[default]
+  Normal,
+  Minimized,
+  Maximized,
+  Fullscreen,
+}
+
+/// Position, size and state of a browser window. Position and size are the outer window, in screen pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowBounds {
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+  pub state: WindowState,
+}
+
+/// Image format for screenshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScreenshotFormat {
+  #[default]
+  Png,
+  Jpeg,
+  Webp,
+}
+
+/// Page setup for `WindowIpc::print_to_pdf`. Sizes are in inches, and the default is US Letter with small margins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfOptions {
+  pub landscape: bool,
+  /// Include background colors and images
+  pub print_background: bool,
+  pub paper_width: f64,
+  pub paper_height: f64,
+  pub margin_top: f64,
+  pub margin_bottom: f64,
+  pub margin_left: f64,
+  pub margin_right: f64,
+  /// HTML for the header of each page. Elements with the classes `date`, `title`, `url`, `pageNumber` and `totalPages`
+  /// have the corresponding values inserted. Chromium only
+  pub header_template: Option<String>,
+  /// HTML for the footer of each page, like `header_template`. Chromium only
+  pub footer_template: Option<String>,
+}
+
+impl Default for PdfOptions {
+  fn default() -> Self {
+    Self {
+      landscape: false,
+      print_background: false,
+      paper_width: 8.5,
+      paper_height: 11.0,
+      margin_top: 0.4,
+      margin_bottom: 0.4,
+      margin_left: 0.4,
+      margin_right: 0.4,
+      header_template: None,
+      footer_template: None,
+    }
+  }
+}
+
+/// An area of the page, in CSS pixels relative to the top-left of the document
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClipRect {
+  pub x: f64,
+  pub y: f64,
+  pub width: f64,
+  pub height: f64,
+}
+
 #[derive(Debug, Clone)]
 pub enum ContentConfig {
   Local(LocalConfig),
@@ -203,6 +295,49 @@ impl IntoContentConfig for RemoteConfig {
   }
 }
 
+/// The arguments for `WindowIpc::call_function`. Tuples of serializable values and `Vec<Value>`s are spread into one
+/// argument per element, and `()` is no arguments. A lone `Value` is always a single argument, even if it's an array or `null`.
+pub trait IntoFunctionArgs {
+  fn into_function_args(self) -> Result<Vec<Value>, CrowserError>;
+}
+
+impl IntoFunctionArgs for Vec<Value> {
+  fn into_function_args(self) -> Result<Vec<Value>, CrowserError> {
+    Ok(self)
+  }
+}
+
+impl IntoFunctionArgs for Value {
+  fn into_function_args(self) -> Result<Vec<Value>, CrowserError> {
+    Ok(vec![self])
+  }
+}
+
+macro_rules! impl_into_function_args {
+  ($($arg:ident),*) => {
+    impl<$($arg: Serialize),*> IntoFunctionArgs for ($($arg,)*) {
+      #[allow(non_snake_case)]
+      fn into_function_args(self) -> Result<Vec<Value>, CrowserError> {
+        let ($($arg,)*) = self;
+
+        Ok(vec![$(serde_json::to_value($arg).map_err(|err| {
+          CrowserError::IpcError(format!("Could not serialize arguments: {}", err))
+        })?),*])
+      }
+    }
+  };
+}
+
+impl_into_function_args!();
+impl_into_function_args!(A);
+impl_into_function_args!(A, B);
+impl_into_function_args!(A, B, C);
+impl_into_function_args!(A, B, C, D);
+impl_into_function_args!(A, B, C, D, E);
+impl_into_function_args!(A, B, C, D, E, F);
+impl_into_function_args!(A, B, C, D, E, F, G);
+impl_into_function_args!(A, B, C, D, E, F, G, H);
+
 /// The Window's IPC interface, used for evalling, events, etc.
 pub struct WindowIpc {
   inner: Arc<Mutex<Option<ipc::BrowserIpc>>>,
@@ -226,12 +361,307 @@ impl WindowIpc {
     }
   }
 
-  /// Eval JavaScript in the window.
-  pub fn eval(&self, script: impl AsRef<str>) -> Result<Value, CrowserError> {
-    let mut ipc = self.inner.lock().unwrap();
+  /// Eval JavaScript in the window, awaiting the result if it's a promise. The result is returned as JSON, or `None` if it's `undefined`.
+  ///
+  /// If the script throws, or the promise rejects, the error is `CrowserError::ScriptError` with the message, location and stack.
+  /// Gives up with `CrowserError::Timeout` after 30 seconds; use `eval_with_timeout` to wait longer or shorter.
+  pub fn eval(&self, script: impl AsRef<str>) -> Result<Option<Value>, CrowserError> {
+    self.eval_with_timeout(script, ipc::DEFAULT_EVAL_TIMEOUT)
+  }
 
-    if let Some(ipc) = ipc.as_mut() {
-      return ipc.eval(script);
+  /// Eval JavaScript in the window like `eval`, giving up with `CrowserError::Timeout` if it hasn't finished within `timeout`.
+  /// The script itself keeps running in the page.
+  pub fn eval_with_timeout(
+    &self,
+    script: impl AsRef<str>,
+    timeout: std::time::Duration,
+  ) -> Result<Option<Value>, CrowserError> {
+    self.browser_ipc()?.eval(script, timeout)
+  }
+
+  /// Eval JavaScript in the window like `eval`, and deserialize the result. `undefined` is deserialized like `null`.
+  ///
+  /// # Example
+  /// ```rust,ignore
+  /// let (width, height): (u32, u32) = ipc.eval_typed("[innerWidth, innerHeight]")?;
+  /// ```
+  pub fn eval_typed<T: DeserializeOwned>(
+    &self,
+    script: impl AsRef<str>,
+  ) -> Result<T, CrowserError> {
+    let result = self.eval(script)?.unwrap_or_default();
+
+    serde_json::from_value(result)
+      .map_err(|err| CrowserError::IpcError(format!("Could not deserialize eval result: {}", err)))
+  }
+
+  /// Call a JavaScript function in the window, given as its source, with arguments serialized from Rust values.
+  /// Unlike formatting values into a script for `eval`, this is safe with untrusted data.
+  ///
+  /// Arguments are given as a tuple, with one element per argument: `("Hello", name)`, or `(vec![1, 2],)` for a single array.
+  /// Pass `()` for no arguments. See `IntoFunctionArgs` for the other ways. The result and timeout are handled like `eval`'s.
+  ///
+  /// # Example
+  /// ```rust,ignore
+  /// let greeting = ipc.call_function("(greeting, name) => `${greeting}, ${name}!`", ("Hello", user_input))?;
+  /// ```
+  pub fn call_function(
+    &self,
+    function: impl AsRef<str>,
+    args: impl IntoFunctionArgs,
+  ) -> Result<Option<Value>, CrowserError> {
+    self.call_function_with_timeout(function, args, ipc::DEFAULT_EVAL_TIMEOUT)
+  }
+
+  /// Call a JavaScript function in the window like `call_function`, giving up with `CrowserError::Timeout` after `timeout`
+  pub fn call_function_with_timeout(
+    &self,
+    function: impl AsRef<str>,
+    args: impl IntoFunctionArgs,
+    timeout: std::time::Duration,
+  ) -> Result<Option<Value>, CrowserError> {
+    let args = args.into_function_args()?;
+
+    self.browser_ipc()?.call_function(function, args, timeout)
+  }
+
+  /// A copy of the IPC connection, so long-running calls don't keep it locked. The copy shares all of its state.
+  fn browser_ipc(&self) -> Result<ipc::BrowserIpc, CrowserError> {
+    match self.inner.lock().unwrap().as_ref() {
+      Some(ipc) => Ok(ipc.clone()),
+      None => Err(CrowserError::IpcError("No IPC".to_string())),
+    }
+  }
+
+  /// Send a raw Chrome DevTools Protocol command to the window's page session, and return the `result` object.
+  ///
+  /// This is an escape hatch for functionality Crowser doesn't wrap itself, like the `Tracing` or `Performance` domains.
+  /// See https://chromedevtools.github.io/devtools-protocol/ for what's available. Support varies between browsers, and
+  /// Firefox-based browsers (which use WebDriver BiDi instead) don't support this at all!
+  pub fn cdp_send(
+    &self,
+    method: impl AsRef<str>,
+    params: impl Serialize,
+  ) -> Result<Value, CrowserError> {
+    self.send_raw(method, params, false)
+  }
+
+  /// Same as `cdp_send`, but sent to the browser session instead of the page, for domains like `Browser` or `SystemInfo`.
+  pub fn cdp_send_browser(
+    &self,
+    method: impl AsRef<str>,
+    params: impl Serialize,
+  ) -> Result<Value, CrowserError> {
+    self.send_raw(method, params, true)
+  }
+
+  /// Subscribe to raw Chrome DevTools Protocol events from the window's page session, such as `Network.requestWillBeSent`.
+  /// Use `"*"` to receive every event from the session.
+  ///
+  /// Remember that many domains need to be enabled (i.e. `Network.enable`) with `cdp_send` before they emit events.
+  /// Events arrive in the order the browser sent them, and dropping the receiver unsubscribes. The receiver holds up to
+  /// 1024 events, and past that new ones are dropped until it's read from again.
+  pub fn cdp_subscribe(
+    &self,
+    method: impl AsRef<str>,
+  ) -> Result<flume::Receiver<CDPEvent>, CrowserError> {
+    self.subscribe_raw(method, false)
+  }
+
+  /// Same as `cdp_subscribe`, but for events from the browser session.
+  pub fn cdp_subscribe_browser(
+    &self,
+    method: impl AsRef<str>,
+  ) -> Result<flume::Receiver<CDPEvent>, CrowserError> {
+    self.subscribe_raw(method, true)
+  }
+
+  fn send_raw(
+    &self,
+    method: impl AsRef<str>,
+    params: impl Serialize,
+    browser: bool,
+  ) -> Result<Value, CrowserError> {
+    let params = serde_json::to_value(params)
+      .map_err(|e| CrowserError::CDPError(format!("Could not serialize params: {}", e)))?;
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.send_raw(method, params, browser);
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  fn subscribe_raw(
+    &self,
+    method: impl AsRef<str>,
+    browser: bool,
+  ) -> Result<flume::Receiver<CDPEvent>, CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.subscribe(method, ipc.session(browser));
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Navigate the window to a new URL. For local content, a URL without a scheme is a path within the local directory,
+  /// such as `settings/index.html`.
+  ///
+  /// This doesn't wait for the page to load. Chromium-based browsers return once the server has responded, and report
+  /// pages that fail to load as errors. Gecko-based browsers return as soon as the navigation has started.
+  pub fn navigate(&self, url: impl AsRef<str>) -> Result<(), CrowserError> {
+    self.browser_ipc()?.navigate(url)
+  }
+
+  /// Reload the current page, optionally bypassing the browser cache.
+  pub fn reload(&self, ignore_cache: bool) -> Result<(), CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.reload(ignore_cache);
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Go back one page in the window's history. Errors if there is nothing to go back to.
+  pub fn go_back(&self) -> Result<(), CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.go_back();
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Go forward one page in the window's history. Errors if there is nothing to go forward to.
+  pub fn go_forward(&self) -> Result<(), CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.go_forward();
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// The URL of the page currently shown in the window.
+  pub fn current_url(&self) -> Result<String, CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.current_url();
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Capture the page as a PNG, JPEG or WebP image. `clip` limits the capture to an area of the document, and `full_page`
+  /// captures the whole document rather than just what is visible. This works in headless mode too.
+  pub fn screenshot(
+    &self,
+    format: ScreenshotFormat,
+    clip: Option<ClipRect>,
+    full_page: bool,
+  ) -> Result<Vec<u8>, CrowserError> {
+    self.browser_ipc()?.screenshot(format, clip, full_page)
+  }
+
+  /// Render the page to a PDF, as if printing it. Browsers that can't are reported as an error from the browser.
+  pub fn print_to_pdf(&self, options: PdfOptions) -> Result<Vec<u8>, CrowserError> {
+    self.browser_ipc()?.print_to_pdf(options)
+  }
+
+  /// Open the browser's print dialog for the page.
+  pub fn print(&self) -> Result<(), CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.print();
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Get the current position, size and state of the window.
+  pub fn bounds(&self) -> Result<WindowBounds, CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.window_bounds();
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Set the position, size and state of the window all at once. Position and size only apply if `state` is `Normal`.
+  pub fn set_bounds(&self, bounds: WindowBounds) -> Result<(), CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.set_window_bounds(bounds);
+    }
+
+    Err(CrowserError::IpcError("No IPC".to_string()))
+  }
+
+  /// Move the window's top-left corner to a position on the screen. Restores the window if it is maximized, etc.
+  pub fn move_to(&self, x: i32, y: i32) -> Result<(), CrowserError> {
+    let bounds = self.bounds()?;
+
+    self.set_bounds(WindowBounds {
+      x,
+      y,
+      state: WindowState::Normal,
+      ..bounds
+    })
+  }
+
+  /// Resize the window. Restores the window if it is maximized, etc.
+  pub fn resize(&self, width: u32, height: u32) -> Result<(), CrowserError> {
+    let bounds = self.bounds()?;
+
+    self.set_bounds(WindowBounds {
+      width,
+      height,
+      state: WindowState::Normal,
+      ..bounds
+    })
+  }
+
+  /// Change the window state, keeping its position and size for when it is restored.
+  pub fn set_state(&self, state: WindowState) -> Result<(), CrowserError> {
+    let bounds = self.bounds()?;
+
+    self.set_bounds(WindowBounds { state, ..bounds })
+  }
+
+  pub fn maximize(&self) -> Result<(), CrowserError> {
+    self.set_state(WindowState::Maximized)
+  }
+
+  pub fn minimize(&self) -> Result<(), CrowserError> {
+    self.set_state(WindowState::Minimized)
+  }
+
+  /// Enter or leave fullscreen.
+  pub fn set_fullscreen(&self, fullscreen: bool) -> Result<(), CrowserError> {
+    match fullscreen {
+      true => self.set_state(WindowState::Fullscreen),
+      false => self.set_state(WindowState::Normal),
+    }
+  }
+
+  /// Ask the browser to close the window.
+  pub fn close(&self) -> Result<(), CrowserError> {
+    let ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_ref() {
+      return ipc.close();
     }
 
     Err(CrowserError::IpcError("No IPC".to_string()))
@@ -266,6 +696,32 @@ impl WindowIpc {
 
     Ok(())
   }
+
+  /// Register a command that only frames from certain origins can invoke, such as the app's own pages but not third-party
+  /// iframes or sites navigated to. Commands registered with `register_command` can be invoked from any frame.
+  ///
+  /// Origins are like `https://example.com`, and may use `*` wildcards (`https://*.example.com`). `self` is the origin of
+  /// the window's local content. Invokes from anywhere else are rejected on the JavaScript side, and logged.
+  ///
+  /// # Example
+  /// ```rust,ignore
+  /// ipc.register_command_with_origins("save", &["self"], |args| save(args))?;
+  /// ```
+  pub fn register_command_with_origins(
+    &self,
+    name: impl AsRef<str>,
+    origins: &[&str],
+    callback: impl FnMut(Value) -> Result<Value, CrowserError> + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    let mut ipc = self.inner.lock().unwrap();
+
+    if let Some(ipc) = ipc.as_mut() {
+      let origins = origins.iter().map(|origin| origin.to_string()).collect();
+      ipc.register_command_with_origins(name, origins, callback)?;
+    }
+
+    Ok(())
+  }
 }
 
 /// The main Window, representing a browser window
@@ -274,7 +730,7 @@ impl WindowIpc {
 /// It contains all configuration, controls, etc. needed to control the window.
 ///
 /// # Example
-/// ```rust
+/// ```rust,ignore
 /// let mut win = Window::new(RemoteConfig {
 ///   url: "https://example.com".to_string(),
 /// }, None, PathBuf::from("/path/to/your/app/profiles"))?;
@@ -299,6 +755,8 @@ pub struct Window {
   created: bool,
 
   webserver_port: Option<u16>,
+  /// Path within the local content to open, relative to its root
+  local_path: String,
 
   config: ContentConfig,
   browser: Browser,
@@ -309,8 +767,23 @@ pub struct Window {
   // Window properties
   width: u32,
   height: u32,
-
-  initialization_script: String,
+  position: Option<(i32, i32)>,
+  centered: bool,
+  min_size: Option<(u32, u32)>,
+  max_size: Option<(u32, u32)>,
+  initial_state: WindowState,
+  remember_geometry: bool,
+  title: Option<String>,
+  icon: Option<PathBuf>,
+  kiosk: bool,
+  frameless: bool,
+  headless: bool,
+  connection_options: ConnectionOptions,
+  navigation_policy: NavigationPolicy,
+  popup_policy: PopupPolicy,
+  console_handlers: ConsoleHandlers,
+
+  initialization_scripts: Vec<String>,
 
   disable_hardware_acceleration: bool,
 
@@ -336,10 +809,20 @@ impl Window {
       }
     };
 
-    Ok(Self {
+    Ok(Self::new_with_browser(config, browser, profile_directory))
+  }
+
+  /// Create a new window using a specific browser, without looking for any installed ones.
+  pub fn new_with_browser(
+    config: impl IntoContentConfig,
+    browser: Browser,
+    profile_directory: PathBuf,
+  ) -> Self {
+    Self {
       profile_directory,
 
       webserver_port: None,
+      local_path: String::new(),
 
       process_handle: None,
 
@@ -350,8 +833,23 @@ impl Window {
 
       width: 800,
       height: 600,
-
-      initialization_script: "".to_string(),
+      position: None,
+      centered: false,
+      min_size: None,
+      max_size: None,
+      initial_state: WindowState::Normal,
+      remember_geometry: false,
+      title: None,
+      icon: None,
+      kiosk: false,
+      frameless: false,
+      headless: false,
+      connection_options: ConnectionOptions::default(),
+      navigation_policy: NavigationPolicy::default(),
+      popup_policy: PopupPolicy::default(),
+      console_handlers: ConsoleHandlers::default(),
+
+      initialization_scripts: vec![],
 
       disable_hardware_acceleration: false,
 
@@ -359,25 +857,46 @@ impl Window {
       chromium_config: None,
 
       ipc: Arc::new(Mutex::new(None)),
-    })
+    }
   }
 
-  /// Set the remote URL for the window, if it is configured to be remote.
+  /// Set the URL the window opens. For local content, this is a path within the local directory, such as `settings/index.html`.
+  /// If the window is already created, it navigates there instead. See `WindowIpc::navigate`.
   pub fn set_url(&mut self, url: impl AsRef<str>) -> Result<(), CrowserError> {
-    if let ContentConfig::Remote(remote) = &mut self.config {
-      remote.url = url.as_ref().to_string();
+    match &mut self.config {
+      ContentConfig::Remote(remote) => remote.url = url.as_ref().to_string(),
+      ContentConfig::Local(_) => self.local_path = url.as_ref().trim_start_matches('/').to_string(),
     }
 
-    let mut ipc = self.ipc.lock().unwrap();
+    let ipc = self.ipc.lock().unwrap();
 
-    if let Some(ipc) = ipc.as_mut() {
-      // TODO this feels wack, there is probably a CDP way to do this
-      ipc.eval(format!("window.location.href = '{}'", url.as_ref()))?;
+    if let Some(ipc) = ipc.as_ref() {
+      ipc.navigate(url)?;
     }
 
     Ok(())
   }
 
+  /// The root of the local content, as served by the webserver. `None` for remote content.
+  fn local_base_url(&self) -> Option<String> {
+    match (&self.config, self.webserver_port) {
+      (ContentConfig::Local(_), Some(port)) => Some(format!("http://localhost:{}/", port)),
+      _ => None,
+    }
+  }
+
+  /// The URL the browser is launched with
+  pub(crate) fn start_url(&self) -> String {
+    match &self.config {
+      ContentConfig::Remote(config) => config.url.clone(),
+      ContentConfig::Local(_) => format!(
+        "{}{}",
+        self.local_base_url().unwrap_or_default(),
+        self.local_path
+      ),
+    }
+  }
+
   /// Manually set the browser to use for the window.
   pub fn set_browser(&mut self, browser: Browser) -> Result<(), CrowserError> {
     if self.created {
@@ -397,8 +916,129 @@ impl Window {
     self.height = height;
   }
 
-  /// Set the initialization script for the window.
-  /// This script will be run when the window is created or the contents are reloaded.
+  /// Set the initial position of the window's top-left corner, in screen pixels.
+  pub fn set_position(&mut self, x: i32, y: i32) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Initial position will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.position = Some((x, y));
+    self.centered = false;
+
+    Ok(())
+  }
+
+  /// Center the window on the screen when it is created. Overrides `set_position`.
+  pub fn center(&mut self) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Centering will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.centered = true;
+
+    Ok(())
+  }
+
+  /// Set the minimum size of the window. Browsers have no native support for this, so Crowser resizes the window back if the user makes it smaller.
+  pub fn set_min_size(&mut self, width: u32, height: u32) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Minimum size will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.min_size = Some((width, height));
+
+    Ok(())
+  }
+
+  /// Set the maximum size of the window. Like `set_min_size`, this is enforced by resizing the window back.
+  pub fn set_max_size(&mut self, width: u32, height: u32) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Maximum size will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.max_size = Some((width, height));
+
+    Ok(())
+  }
+
+  /// Set the state the window starts in, such as maximized or fullscreen.
+  pub fn set_initial_state(&mut self, state: WindowState) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Initial state will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.initial_state = state;
+
+    Ok(())
+  }
+
+  /// Save the window's position, size and state to the profile directory, and restore them the next time the window is created.
+  /// Anything set with `set_size`, `set_position`, etc. is only used when nothing has been saved yet.
+  ///
+  /// The geometry is saved when Crowser closes the window. When the user closes it, the browser is gone before it can be
+  /// asked, so changes are also saved every couple of seconds while the window is open.
+  pub fn set_remember_geometry(&mut self, remember: bool) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Remembering geometry will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.remember_geometry = remember;
+
+    Ok(())
+  }
+
+  /// Set the window title. This overrides the page's `document.title`, including after navigating to another page.
+  pub fn set_title(&mut self, title: impl AsRef<str>) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Title will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.title = Some(title.as_ref().to_string());
+
+    Ok(())
+  }
+
+  /// Set the window icon from an image file (PNG, ICO, SVG, etc.). This replaces the page's favicon,
+  /// which Chromium uses as the app window icon. Gecko browsers are also given the icon in the profile's
+  /// `chrome/icons/default` folder, which some builds and platforms use for the window icon.
+  pub fn set_icon(&mut self, path: PathBuf) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Icon will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    if !path.is_file() {
+      return Err(CrowserError::IoError(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("Icon not found: {}", path.display()),
+      )));
+    }
+
+    self.icon = Some(path);
+
+    Ok(())
+  }
+
+  /// Set the initialization script for the window, replacing any previously added ones.
+  /// This script runs before any of the page's own scripts, in every frame, on every navigation.
+  ///
+  /// Top-level `var` and `function` declarations become globals the page can use. Gecko-based browsers run the script
+  /// through `eval`, where `let` and `const` stay local to it, so assign to `window` for globals that must work everywhere.
   pub fn set_initialization_script(&mut self, script: impl AsRef<str>) -> Result<(), CrowserError> {
     if self.created {
       return Err(CrowserError::DoAfterCreate(
@@ -406,7 +1046,23 @@ impl Window {
       ));
     }
 
-    self.initialization_script = script.as_ref().to_string();
+    self.initialization_scripts = vec![script.as_ref().to_string()];
+
+    Ok(())
+  }
+
+  /// Add another initialization script to the window. Scripts run in the order they were added,
+  /// after Crowser's own IPC script, so `window.__CROWSER` is always available to them.
+  pub fn add_initialization_script(&mut self, script: impl AsRef<str>) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Initialization script will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self
+      .initialization_scripts
+      .push(script.as_ref().to_string());
 
     Ok(())
   }
@@ -416,6 +1072,206 @@ impl Window {
     WindowIpc::new(self.ipc.clone())
   }
 
+  /// Run the window in kiosk mode: fullscreen, with no way for the user to leave fullscreen or reach the browser UI.
+  /// Position, size and initial state are ignored in kiosk mode.
+  pub fn set_kiosk(&mut self, kiosk: bool) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Kiosk mode will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.kiosk = kiosk;
+
+    Ok(())
+  }
+
+  /// Remove the browser's own UI, leaving only the OS title bar and the page. Only Gecko-based browsers need this, where
+  /// it hides the tab strip. Chromium app windows always look like this, so for Chromium-based browsers it does nothing
+  /// except log a warning under the `browser` target. Neither browser can remove the OS title bar itself; use kiosk mode
+  /// for a window with no frame at all.
+  pub fn set_frameless(&mut self, frameless: bool) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Frameless mode will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.frameless = frameless;
+
+    Ok(())
+  }
+
+  /// Run the browser without a visible window, for CI or server-side rendering. Everything else works as usual,
+  /// including IPC, eval, and serving local content. Window position, state and geometry saving are ignored.
+  pub fn set_headless(&mut self, headless: bool) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Headless mode will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.headless = headless;
+
+    Ok(())
+  }
+
+  /// Record every CDP frame sent to and received from the browser to a JSONL file, to debug issues with a particular browser.
+  /// The recording can be played back with `crowser::test_support::replay`, which needs the `test-support` feature.
+  /// Only Chromium-based browsers speak CDP, so Gecko-based ones record nothing, and a warning is logged instead.
+  pub fn set_cdp_recording(&mut self, path: PathBuf) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "CDP recording will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.connection_options.recording = Some(path);
+
+    Ok(())
+  }
+
+  /// Log every protocol frame sent to and received from the browser, at trace level under the `cdp` target.
+  /// This is very noisy (the page is polled for IPC calls every few milliseconds), so it's off unless asked for.
+  pub fn set_protocol_tracing(&mut self, tracing: bool) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Protocol tracing will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.connection_options.tracing = tracing;
+
+    Ok(())
+  }
+
+  /// Allow the window to navigate to an origin (`https://example.com`) or URL pattern with `*` wildcards (`https://*.example.com/docs/*`).
+  ///
+  /// The scheme, host, port and path are matched separately. In the host, a leading `*` matches any subdomain and a `*`
+  /// elsewhere matches a single label; in the path, `*` matches anything. Patterns without a path allow the whole origin.
+  ///
+  /// Once anything is allowed, or a callback is set with `on_navigation`, the window may only navigate within the app's own
+  /// origin, the allowed origins, and whatever the callback allows. Other links are opened in the user's default browser.
+  pub fn allow_navigation(&mut self, pattern: impl AsRef<str>) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Navigation policy will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self
+      .navigation_policy
+      .allowed
+      .push(pattern.as_ref().to_string());
+
+    Ok(())
+  }
+
+  /// Decide what happens when the page tries to navigate somewhere not allowed by `allow_navigation`.
+  /// The callback receives the URL, and only applies to the top-level page, not iframes.
+  pub fn on_navigation(
+    &mut self,
+    callback: impl Fn(&str) -> NavigationAction + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Navigation policy will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.navigation_policy.callback = Some(Arc::new(callback));
+
+    Ok(())
+  }
+
+  /// Decide what happens when the page opens a new window, with `window.open` or a `target="_blank"` link for example.
+  /// The callback receives the popup's URL. Without this, popups are left as plain browser windows that Crowser doesn't manage.
+  ///
+  /// The popup's window has already been opened by the time the callback runs, so denied popups may briefly appear before
+  /// closing. On Chromium-based browsers the popup is held before loading anything until the callback allows it, so denied
+  /// popups never run any scripts. Gecko-based browsers have already started loading it.
+  pub fn on_popup(
+    &mut self,
+    callback: impl Fn(&str) -> PopupAction + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Popup handling will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.popup_policy.callback = Some(Arc::new(callback));
+
+    Ok(())
+  }
+
+  /// Receive the IPC for every popup kept with `PopupAction::OpenWindow`, which has its own commands, listeners, etc.
+  /// If this is set without `on_popup`, every popup is kept as a window.
+  ///
+  /// Popups get the window's initialization scripts, title and icon, and its navigation policy, which also allows the
+  /// popup's own origin. They aren't reloaded to set this up, so the scripts also run in the page the popup is already showing.
+  pub fn on_popup_window(
+    &mut self,
+    callback: impl Fn(WindowIpc) + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Popup handling will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.popup_policy.on_window = Some(Arc::new(callback));
+
+    Ok(())
+  }
+
+  /// Receive everything the page logs to the console, with `console.log`, `console.error`, etc.
+  /// Popups kept with `PopupAction::OpenWindow` report to the same callback.
+  pub fn on_console(
+    &mut self,
+    callback: impl Fn(&ConsoleMessage) + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Console handling will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.console_handlers.on_message = Some(Arc::new(callback));
+
+    Ok(())
+  }
+
+  /// Receive every error the page throws without catching, including unhandled promise rejections.
+  pub fn on_exception(
+    &mut self,
+    callback: impl Fn(&PageException) + Send + Sync + 'static,
+  ) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Exception handling will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.console_handlers.on_exception = Some(Arc::new(callback));
+
+    Ok(())
+  }
+
+  /// Log the page's console output and uncaught exceptions with the `log` crate, under the `console` target,
+  /// so frontend errors show up alongside the app's own logs. This works with or without `on_console` and `on_exception`.
+  pub fn set_forward_console(&mut self, forward: bool) -> Result<(), CrowserError> {
+    if self.created {
+      return Err(CrowserError::DoAfterCreate(
+        "Console forwarding will have no effect if window is already created".to_string(),
+      ));
+    }
+
+    self.console_handlers.forward_to_log = forward;
+
+    Ok(())
+  }
+
   /// Disable hardware acceleration in the browser window.
   pub fn disable_hardware_acceleration(&mut self) -> Result<(), CrowserError> {
     if self.created {
@@ -469,31 +1325,127 @@ impl Window {
     let (w_tx, w_rx) = std::sync::mpsc::channel::<WebserverMessage>();
     let webserver_thread = std::thread::spawn(move || {
       if let ContentConfig::Local(config) = t_config {
-        let webserver = Webserver::new(port, config.directory);
+        let webserver = match Webserver::new(port, config.directory) {
+          Ok(webserver) => webserver,
+          Err(err) => {
+            log::error!(target: "webserver", "Could not start webserver: {}", err);
+            return;
+          }
+        };
+
+        log::info!(target: "webserver", "Webserver started on port {}", port);
 
-        println!("Webserver started on port {}", port);
+        loop {
+          // Small delay to prevent a tight loop
+          std::thread::sleep(std::time::Duration::from_millis(1));
 
-        if let Ok(webserver) = webserver {
-          loop {
-            // Small delay to prevent a tight loop
-            std::thread::sleep(std::time::Duration::from_millis(1));
+          if let Ok(WebserverMessage::Kill) = w_rx.try_recv() {
+            break;
+          }
 
-            if let Ok(WebserverMessage::Kill) = w_rx.try_recv() {
+          match webserver.poll_request() {
+            Ok(_) => {}
+            Err(err) => {
+              log::error!(target: "webserver", "Webserver error: {}", err);
               break;
             }
+          };
+        }
+      }
+    });
+
+    let restored = match self.remember_geometry && !self.kiosk && !self.headless {
+      true => util::geometry::load(&self.profile_directory),
+      false => None,
+    };
+
+    if let Some(saved) = restored {
+      self.position = Some((saved.x, saved.y));
+      self.width = saved.width;
+      self.height = saved.height;
+      self.initial_state = saved.state;
+      self.centered = false;
+    }
+
+    let terminated = Arc::new(AtomicBool::new(false));
+
+    // Don't leave the browser or the webserver running behind an error
+    if let Err(err) = self.start_browser(restored, &terminated) {
+      if let Some(child) = self.process_handle.take() {
+        child.kill().unwrap_or_default();
+        let _ = child.wait();
+      }
+
+      w_tx.send(WebserverMessage::Kill).unwrap_or_default();
+      webserver_thread.join().unwrap_or_default();
+
+      self.ipc.lock().unwrap().take();
+      self.created = false;
+
+      return Err(err);
+    }
+
+    loop {
+      std::thread::sleep(std::time::Duration::from_secs(1));
+
+      // Either we've been told to stop, or the window's page is gone and the browser may not exit by itself
+      if terminated.load(std::sync::atomic::Ordering::Relaxed) || self.page_closed() {
+        log::info!(target: "browser", "Window closed, stopping the browser");
+
+        // Only possible if we're the ones closing the window, since the browser still has it open
+        if let Err(err) = self.save_geometry() {
+          log::debug!(target: "browser", "Could not save the window geometry: {}", err);
+        }
+
+        // Kill the process
+        if let Some(child) = self.process_handle.as_ref() {
+          child.kill()?;
+        }
 
-            match webserver.poll_request() {
-              Ok(_) => {}
-              Err(err) => {
-                eprintln!("Webserver error: {}", err);
-                break;
-              }
-            };
+        match w_tx.send(WebserverMessage::Kill) {
+          Ok(_) => {}
+          Err(_) => {
+            // TODO This likely means the thread is already dead
           }
         }
+
+        webserver_thread.join()?;
+        break;
       }
-    });
 
+      // if the process is dead, break
+      if let Some(child) = self.process_handle.as_ref() {
+        if let Some(status) = child.try_wait()? {
+          log::info!(target: "browser", "Browser exited ({})", status);
+
+          match w_tx.send(WebserverMessage::Kill) {
+            Ok(_) => {}
+            Err(_) => {
+              // TODO This likely means the thread is already dead
+            }
+          }
+
+          webserver_thread.join()?;
+          break;
+        }
+      } else {
+        break;
+      }
+    }
+
+    // If we have broken out of the loop, the window is closed
+    self.ipc.lock().unwrap().take();
+    self.created = false;
+
+    Ok(())
+  }
+
+  /// Launch the browser, connect to it, and get the window's page ready
+  fn start_browser(
+    &mut self,
+    restored: Option<WindowBounds>,
+    terminated: &Arc<AtomicBool>,
+  ) -> Result<(), CrowserError> {
     let browser_path = get_browser_path(&self.browser);
 
     if browser_path.is_none() {
@@ -503,7 +1455,7 @@ impl Window {
     }
 
     let browser_path = browser_path.unwrap();
-    let mut cmd: std::process::Command = std::process::Command::new(browser_path);
+    let mut cmd: std::process::Command = std::process::Command::new(&browser_path);
     let mut args = match self.browser.kind {
       BrowserKind::Chromium => browser::chromium::generate_cli_options(self),
       BrowserKind::Gecko => browser::firefox::generate_cli_options(self),
@@ -515,6 +1467,9 @@ impl Window {
 
     args.push("--remote-debugging-port=".to_string() + &remote_debugging_port.to_string());
 
+    log::info!(target: "browser", "Launching {} ({})", self.browser.name, browser_path.display());
+    log::debug!(target: "browser", "Browser arguments: {:?}", args);
+
     cmd.args(args);
 
     match self.browser.kind {
@@ -524,12 +1479,45 @@ impl Window {
     }
 
     let process = cmd.spawn()?;
-    let terminated = Arc::new(AtomicBool::new(false));
 
     self.process_handle = Some(SharedChild::new(process)?);
 
     // Now that the process is running, we can start attempting to connect to it with IPC
-    let ipc = ipc::BrowserIpc::new(remote_debugging_port, self.initialization_script.clone())?;
+    let mut initialization_scripts = self.initialization_scripts.clone();
+
+    if let Some(script) = self.branding_script()? {
+      initialization_scripts.insert(0, script);
+    }
+
+    let mut ipc = ipc::BrowserIpc::new(
+      remote_debugging_port,
+      initialization_scripts.clone(),
+      self.browser.kind,
+      self.local_base_url(),
+      &self.connection_options,
+      self.console_handlers.clone(),
+    )?;
+    if self.navigation_policy.is_active() {
+      let mut policy = self.navigation_policy.clone();
+
+      // The app's own pages are always allowed
+      if let Some(origin) = navigation::origin(&self.start_url()) {
+        policy.allowed.push(origin);
+      }
+
+      ipc.set_navigation_policy(policy)?;
+    }
+
+    // Popups are branded like the window itself, so they look like part of the app
+    if self.popup_policy.is_active() {
+      ipc.set_popup_policy(self.popup_policy.clone(), initialization_scripts)?;
+    }
+
+    // The window is perfectly usable without it, so geometry that can't be applied isn't worth failing over
+    if let Err(err) = self.apply_geometry(&ipc, restored) {
+      log::warn!(target: "browser", "Could not apply the window geometry: {}", err);
+    }
+
     self.ipc.lock().unwrap().replace(ipc);
 
     for signal in &[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
@@ -537,46 +1525,187 @@ impl Window {
       signal_hook::flag::register(*signal, terminated)?;
     }
 
-    loop {
-      std::thread::sleep(std::time::Duration::from_secs(1));
+    Ok(())
+  }
 
-      if terminated.load(std::sync::atomic::Ordering::Relaxed) {
-        // Kill the process
-        if let Some(child) = self.process_handle.as_ref() {
-          child.kill()?;
-        }
+  /// Save the window's current geometry, if it's being remembered
+  fn save_geometry(&self) -> Result<(), CrowserError> {
+    if !self.remember_geometry || self.kiosk || self.headless {
+      return Ok(());
+    }
 
-        match w_tx.send(WebserverMessage::Kill) {
-          Ok(_) => {}
-          Err(_) => {
-            // TODO This likely means the thread is already dead
-          }
-        }
+    let bounds = match self.ipc.lock().unwrap().as_ref() {
+      Some(ipc) => ipc.window_bounds()?,
+      None => return Ok(()),
+    };
+    let previous = util::geometry::load(&self.profile_directory);
 
-        webserver_thread.join()?;
-        break;
+    util::geometry::save(
+      &self.profile_directory,
+      &util::geometry::merge(previous, bounds),
+    )
+  }
+
+  /// Whether the browser has reported that the window's page was closed
+  fn page_closed(&self) -> bool {
+    match self.ipc.lock().unwrap().as_ref() {
+      Some(ipc) => ipc.is_closed(),
+      None => false,
+    }
+  }
+
+  /// The script that applies the custom title and icon, if either is set
+  fn branding_script(&self) -> Result<Option<String>, CrowserError> {
+    if self.title.is_none() && self.icon.is_none() {
+      return Ok(None);
+    }
+
+    // Embedding the icon works for remote pages too, where there is no webserver of our own to serve it from
+    let icon = match &self.icon {
+      Some(path) => {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let data = util::base64::encode(&std::fs::read(path)?);
+
+        Some(format!("data:{};base64,{}", mime, data))
       }
+      None => None,
+    };
 
-      // if the process is dead, break
-      if let Some(child) = self.process_handle.as_ref() {
-        if child.try_wait()?.is_some() {
-          match w_tx.send(WebserverMessage::Kill) {
-            Ok(_) => {}
-            Err(_) => {
-              // TODO This likely means the thread is already dead
-            }
-          }
+    Ok(Some(format!(
+      "({})({}, {})",
+      util::javascript::BRANDING_JS,
+      serde_json::to_string(&self.title).unwrap_or_default(),
+      serde_json::to_string(&icon).unwrap_or_default(),
+    )))
+  }
 
-          webserver_thread.join()?;
-          break;
+  /// Apply whatever geometry couldn't be passed on the command line, and start enforcing size limits and saving geometry if enabled.
+  fn apply_geometry(
+    &self,
+    ipc: &ipc::BrowserIpc,
+    restored: Option<WindowBounds>,
+  ) -> Result<(), CrowserError> {
+    // Gecko only honours `--window-size` when taking a screenshot from the command line, so its headless pages are
+    // sized here instead. Chromium sizes them from the command line
+    if self.headless && self.browser.kind == BrowserKind::Gecko {
+      ipc.set_viewport(self.width, self.height)?;
+    }
+
+    // Kiosk windows are always fullscreen and can't be moved or resized, and headless windows are never seen
+    if self.kiosk || self.headless {
+      return Ok(());
+    }
+
+    let mut position = self.position;
+
+    if self.centered {
+      if let Some(area) = util::geometry::screen_area(ipc) {
+        position = Some(util::geometry::center_in(area, self.width, self.height));
+      }
+    }
+
+    // Chromium already got everything except centering from the command line. Gecko has no flags for any of it
+    if self.browser.kind != BrowserKind::Chromium || self.centered {
+      let current = ipc.window_bounds()?;
+      let (x, y) = position.unwrap_or((current.x, current.y));
+
+      ipc.set_window_bounds(WindowBounds {
+        x,
+        y,
+        width: self.width,
+        height: self.height,
+        state: WindowState::Normal,
+      })?;
+
+      if self.initial_state != WindowState::Normal {
+        ipc.set_window_bounds(WindowBounds {
+          state: self.initial_state,
+          ..current
+        })?;
+      }
+    }
+
+    // The saved position may be on a monitor that has since been unplugged. If so, start in the middle of the current one instead
+    if let Some(saved) = restored {
+      if let Some(area) = util::geometry::screen_area(ipc) {
+        let current = ipc.window_bounds()?;
+
+        if !util::geometry::is_on_screen(area, &current) {
+          let width = saved.width.min(area.2);
+          let height = saved.height.min(area.3);
+          let (x, y) = util::geometry::center_in(area, width, height);
+
+          ipc.set_window_bounds(WindowBounds {
+            x,
+            y,
+            width,
+            height,
+            state: WindowState::Normal,
+          })?;
+
+          if saved.state != WindowState::Normal {
+            ipc.set_window_bounds(WindowBounds {
+              state: saved.state,
+              ..current
+            })?;
+          }
         }
-      } else {
-        break;
       }
     }
 
-    // If we have broken out of the loop, the window is closed
-    self.created = false;
+    if self.min_size.is_none() && self.max_size.is_none() && !self.remember_geometry {
+      return Ok(());
+    }
+
+    let ipc = ipc.clone();
+    let limited = self.min_size.is_some() || self.max_size.is_some();
+    let min = self.min_size.unwrap_or((0, 0));
+    let max = self.max_size.unwrap_or((u32::MAX, u32::MAX));
+    let profile_directory = self
+      .remember_geometry
+      .then(|| self.profile_directory.clone());
+    let mut saved = restored;
+    let mut last_save = std::time::Instant::now();
+
+    // There is no resize event to listen to, so check every so often. Stops once the window is gone.
+    // Geometry is saved when we close the window ourselves, but when the user closes it the browser is already gone by
+    // the time we find out, so it's also saved here, far less often than size limits are enforced and only when it changed
+    std::thread::spawn(move || loop {
+      std::thread::sleep(match limited {
+        true => util::geometry::SIZE_LIMIT_INTERVAL,
+        false => util::geometry::GEOMETRY_SAVE_INTERVAL,
+      });
+
+      let mut bounds = match ipc.window_bounds() {
+        Ok(bounds) => bounds,
+        Err(_) => break,
+      };
+
+      if bounds.state == WindowState::Normal {
+        let width = bounds.width.max(min.0).min(max.0);
+        let height = bounds.height.max(min.1).min(max.1);
+
+        if width != bounds.width || height != bounds.height {
+          bounds.width = width;
+          bounds.height = height;
+          ipc.set_window_bounds(bounds).unwrap_or_default();
+        }
+      }
+
+      if let Some(profile_directory) = &profile_directory {
+        if last_save.elapsed() < util::geometry::GEOMETRY_SAVE_INTERVAL {
+          continue;
+        }
+
+        last_save = std::time::Instant::now();
+        let merged = util::geometry::merge(saved, bounds);
+
+        if saved != Some(merged) {
+          util::geometry::save(profile_directory, &merged).unwrap_or_default();
+          saved = Some(merged);
+        }
+      }
+    });
 
     Ok(())
   }
@@ -609,3 +1738,29 @@ impl Window {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn function_args_have_an_explicit_shape() {
+    assert_eq!(().into_function_args().unwrap(), Vec::<Value>::new());
+    assert_eq!(
+      ("a", 1).into_function_args().unwrap(),
+      [json!("a"), json!(1)]
+    );
+    assert_eq!((vec![1, 2],).into_function_args().unwrap(), [json!([1, 2])]);
+    assert_eq!((Value::Null,).into_function_args().unwrap(), [Value::Null]);
+    assert_eq!(
+      vec![json!(1), json!(2)].into_function_args().unwrap(),
+      [json!(1), json!(2)]
+    );
+
+    // A lone value is never spread or dropped
+    assert_eq!(json!([1, 2]).into_function_args().unwrap(), [json!([1, 2])]);
+    assert_eq!(Value::Null.into_function_args().unwrap(), [Value::Null]);
+  }
+}
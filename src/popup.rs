@@ -0,0 +1,48 @@
+use std::{fmt::Debug, sync::Arc};
+
+use crate::WindowIpc;
+
+/// What to do with a window the page tries to open, with `window.open` or a `target="_blank"` link for example
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopupAction {
+  /// Close the popup
+  Deny,
+  /// Keep the popup as a window of its own, with its own IPC. See `Window::on_popup_window`
+  OpenWindow,
+  /// Close the popup, and open the URL in the user's default browser instead
+  OpenExternal,
+}
+
+pub type PopupCallback = Arc<dyn Fn(&str) -> PopupAction + Send + Sync + 'static>;
+pub type PopupWindowCallback = Arc<dyn Fn(WindowIpc) + Send + Sync + 'static>;
+
+/// Decides what happens to popups, and gives the app the IPC for the ones that are kept
+#[derive(Default, Clone)]
+pub struct PopupPolicy {
+  pub(crate) callback: Option<PopupCallback>,
+  pub(crate) on_window: Option<PopupWindowCallback>,
+}
+
+impl Debug for PopupPolicy {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PopupPolicy")
+      .field("callback", &self.callback.is_some())
+      .field("on_window", &self.on_window.is_some())
+      .finish()
+  }
+}
+
+impl PopupPolicy {
+  /// Whether popups are managed at all. If not, they are left as plain browser windows
+  pub fn is_active(&self) -> bool {
+    self.callback.is_some() || self.on_window.is_some()
+  }
+
+  /// Without a callback, every popup becomes a window
+  pub fn decide(&self, url: &str) -> PopupAction {
+    match &self.callback {
+      Some(callback) => callback(url),
+      None => PopupAction::OpenWindow,
+    }
+  }
+}
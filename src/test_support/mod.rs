@@ -0,0 +1,443 @@
+/*!
+# Test support
+
+A fake browser for testing Crowser (and apps built on it) without a real one installed. It serves `/json/version` and a
+CDP WebSocket endpoint, and answers commands the way a Chromium-based browser with a single page would.
+
+Responses can be scripted per method with `FakeBrowser::on`, events can be pushed with `FakeBrowser::emit`, and every
+command received is recorded for assertions.
+
+```rust,ignore
+let browser = FakeBrowser::start();
+browser.on("Runtime.evaluate", |_| Ok(serde_json::json!({ "result": { "type": "number", "value": 2 } })));
+
+// ...connect to `browser.port()`...
+
+assert!(browser.wait_for("Page.reload", Duration::from_secs(1)).is_some());
+```
+
+The `crowser-fake-browser` binary wraps this in a program that `Window::create` can launch, using `fake_browser`
+to describe it.
+
+Sessions recorded from a real browser with `Window::set_cdp_recording` can be played back with `replay`, to reproduce
+(and regression test) problems with browsers that aren't installed.
+*/
+
+use std::{
+  collections::HashMap,
+  io::{Read, Write},
+  net::{TcpListener, TcpStream},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use tungstenite::Message;
+
+use crate::{
+  backend::cdp::CdpBackend,
+  browser::{Browser, BrowserKind, BrowserWindowsConfig},
+  console::ConsoleHandlers,
+  error::CrowserError,
+  ipc::BrowserIpc,
+  WindowIpc,
+};
+
+/// The page target the fake browser pretends to have
+pub const PAGE_TARGET_ID: &str = "fake-page";
+pub const PAGE_SESSION_ID: &str = "fake-page-session";
+pub const BROWSER_SESSION_ID: &str = "fake-browser-session";
+/// The page's only execution context, reported once `Runtime` is enabled
+pub const PAGE_CONTEXT_ID: i64 = 1;
+
+/// A command received by the fake browser
+#[derive(Debug, Clone)]
+pub struct FakeCommand {
+  pub method: String,
+  pub params: Value,
+  pub session_id: Option<String>,
+}
+
+/// Produces the `result` of a command, or an error message
+pub type FakeHandler = Box<dyn FnMut(&FakeCommand) -> Result<Value, String> + Send>;
+
+#[derive(Default)]
+struct FakeState {
+  handlers: HashMap<String, FakeHandler>,
+  received: Vec<FakeCommand>,
+  connections: Vec<flume::Sender<Message>>,
+  greetings: Vec<Message>,
+}
+
+/// A scripted stand-in for a Chromium-based browser's remote debugging endpoint
+#[derive(Clone)]
+pub struct FakeBrowser {
+  port: u16,
+  state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBrowser {
+  /// Start on any free port
+  pub fn start() -> Self {
+    Self::start_on(0)
+  }
+
+  /// Start on a specific port, like a browser given `--remote-debugging-port`
+  pub fn start_on(port: u16) -> Self {
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("Fake browser could not bind");
+    let port = listener
+      .local_addr()
+      .map(|addr| addr.port())
+      .unwrap_or(port);
+    let browser = FakeBrowser {
+      port,
+      state: Arc::new(Mutex::new(FakeState::default())),
+    };
+
+    let t_browser = browser.clone();
+    std::thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let browser = t_browser.clone();
+        std::thread::spawn(move || browser.handle_connection(stream));
+      }
+    });
+
+    browser
+  }
+
+  pub fn port(&self) -> u16 {
+    self.port
+  }
+
+  /// Script the response to a method, replacing the default (and any previous) one
+  pub fn on(
+    &self,
+    method: impl AsRef<str>,
+    handler: impl FnMut(&FakeCommand) -> Result<Value, String> + Send + 'static,
+  ) {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .handlers
+      .insert(method.as_ref().to_string(), Box::new(handler));
+  }
+
+  /// Send an event to every connected client
+  pub fn emit(&self, method: impl AsRef<str>, params: Value, session_id: Option<&str>) {
+    let mut event = serde_json::json!({ "method": method.as_ref(), "params": params });
+
+    if let Some(session_id) = session_id {
+      event["sessionId"] = Value::from(session_id);
+    }
+
+    self.broadcast(event);
+  }
+
+  /// Send an event to every client that connects from now on, right behind the handshake response
+  pub fn greet(&self, method: impl AsRef<str>, params: Value) {
+    let event = serde_json::json!({ "method": method.as_ref(), "params": params });
+
+    self
+      .state
+      .lock()
+      .unwrap()
+      .greetings
+      .push(Message::Text(event.to_string()));
+  }
+
+  /// Every command received so far, in order
+  pub fn received(&self) -> Vec<FakeCommand> {
+    self.state.lock().unwrap().received.clone()
+  }
+
+  /// Wait for a command to be received (or to have been received already)
+  pub fn wait_for(&self, method: impl AsRef<str>, timeout: Duration) -> Option<FakeCommand> {
+    self.wait_for_matching(|cmd| cmd.method == method.as_ref(), timeout)
+  }
+
+  /// Wait for a command matching a predicate to be received (or to have been received already)
+  pub fn wait_for_matching(
+    &self,
+    predicate: impl Fn(&FakeCommand) -> bool,
+    timeout: Duration,
+  ) -> Option<FakeCommand> {
+    let start = Instant::now();
+
+    while start.elapsed() < timeout {
+      if let Some(cmd) = self.received().into_iter().find(|cmd| predicate(cmd)) {
+        return Some(cmd);
+      }
+
+      std::thread::sleep(Duration::from_millis(5));
+    }
+
+    None
+  }
+
+  /// Close every connection, like a browser that has exited
+  pub fn disconnect(&self) {
+    let connections = std::mem::take(&mut self.state.lock().unwrap().connections);
+
+    for tx in connections {
+      tx.send(Message::Close(None)).unwrap_or_default();
+    }
+  }
+
+  /// Whether any client is still connected
+  pub fn is_connected(&self) -> bool {
+    let mut state = self.state.lock().unwrap();
+    state.connections.retain(|tx| !tx.is_disconnected());
+
+    !state.connections.is_empty()
+  }
+
+  fn broadcast(&self, message: Value) {
+    let text = message.to_string();
+    let mut state = self.state.lock().unwrap();
+
+    state
+      .connections
+      .retain(|tx| tx.send(Message::Text(text.clone())).is_ok());
+  }
+
+  fn handle_connection(&self, mut stream: TcpStream) {
+    // Peek at the request to see whether it's for the HTTP endpoint or the WebSocket one, leaving it for the WebSocket handshake
+    let mut head = [0u8; 1024];
+    let len = stream.peek(&mut head).unwrap_or_default();
+    let head = String::from_utf8_lossy(&head[..len]);
+
+    if head.starts_with("GET /json") {
+      let mut request = [0u8; 1024];
+      stream.read(&mut request).unwrap_or_default();
+
+      // The WebSocket URL is last, since Crowser reads it up to the closing brace
+      let body = format!(
+        "{{\"Browser\": \"FakeBrowser/1.0\", \"Protocol-Version\": \"1.3\", \"webSocketDebuggerUrl\": \"ws://127.0.0.1:{}/devtools/browser/fake\"}}",
+        self.port
+      );
+      let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+      );
+
+      stream.write_all(response.as_bytes()).unwrap_or_default();
+      return;
+    }
+
+    // Registered before the handshake finishes, so events emitted as soon as a client has connected aren't lost
+    let (tx, rx) = flume::unbounded();
+    {
+      let mut state = self.state.lock().unwrap();
+
+      for greeting in &state.greetings {
+        tx.send(greeting.clone()).unwrap_or_default();
+      }
+
+      state.connections.push(tx);
+    }
+
+    let mut ws = match tungstenite::accept(stream) {
+      Ok(ws) => ws,
+      Err(_) => return,
+    };
+
+    // Reads time out regularly so outgoing messages can be written in between
+    ws.get_ref()
+      .set_read_timeout(Some(Duration::from_millis(5)))
+      .unwrap_or_default();
+
+    loop {
+      for message in rx.try_iter() {
+        let closing = matches!(message, Message::Close(_));
+
+        if ws.send(message).is_err() || closing {
+          return;
+        }
+      }
+
+      match ws.read() {
+        Ok(Message::Text(text)) => self.handle_command(&text),
+        Ok(Message::Close(_)) => return,
+        Ok(_) => {}
+        Err(tungstenite::Error::Io(err))
+          if matches!(
+            err.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+          ) => {}
+        Err(_) => return,
+      }
+    }
+  }
+
+  fn handle_command(&self, text: &str) {
+    let message: Value = match serde_json::from_str(text) {
+      Ok(message) => message,
+      Err(_) => return,
+    };
+
+    let cmd = FakeCommand {
+      method: message["method"].as_str().unwrap_or_default().to_string(),
+      params: message["params"].clone(),
+      session_id: message["sessionId"].as_str().map(|s| s.to_string()),
+    };
+
+    // Scripted handlers are taken out while they run, so they can use the fake browser themselves
+    let handler = {
+      let mut state = self.state.lock().unwrap();
+      state.received.push(cmd.clone());
+      state.handlers.remove(&cmd.method)
+    };
+
+    let result = match handler {
+      Some(mut handler) => {
+        let result = handler(&cmd);
+        self
+          .state
+          .lock()
+          .unwrap()
+          .handlers
+          .entry(cmd.method.clone())
+          .or_insert(handler);
+        result
+      }
+      None => self.default_response(&cmd),
+    };
+
+    let mut response = match result {
+      Ok(result) => serde_json::json!({ "id": message["id"], "result": result }),
+      Err(error) => serde_json::json!({
+        "id": message["id"],
+        "error": { "code": -32000, "message": error },
+      }),
+    };
+
+    if let Some(session_id) = &cmd.session_id {
+      response["sessionId"] = Value::from(session_id.as_str());
+    }
+
+    self.broadcast(response);
+
+    // Enabling Runtime reports the contexts that already exist, which for a blank page is only its own
+    if cmd.method == "Runtime.enable" && cmd.session_id.as_deref() == Some(PAGE_SESSION_ID) {
+      self.emit(
+        "Runtime.executionContextCreated",
+        serde_json::json!({
+          "context": {
+            "id": PAGE_CONTEXT_ID,
+            "origin": "null",
+            "name": "",
+            "auxData": { "isDefault": true, "type": "default", "frameId": PAGE_TARGET_ID },
+          },
+        }),
+        Some(PAGE_SESSION_ID),
+      );
+    }
+
+    // Closing the page also destroys its target
+    if cmd.method == "Target.closeTarget" {
+      self.emit(
+        "Target.targetDestroyed",
+        serde_json::json!({ "targetId": cmd.params["targetId"] }),
+        None,
+      );
+    }
+  }
+
+  /// How a browser with a single, blank page would respond
+  fn default_response(&self, cmd: &FakeCommand) -> Result<Value, String> {
+    let result = match cmd.method.as_str() {
+      "Target.getTargets" => serde_json::json!({
+        "targetInfos": [{
+          "targetId": PAGE_TARGET_ID,
+          "type": "page",
+          "title": "",
+          "url": "about:blank",
+          "attached": false,
+        }],
+      }),
+      "Target.attachToTarget" => serde_json::json!({ "sessionId": PAGE_SESSION_ID }),
+      "Target.attachToBrowserTarget" => serde_json::json!({ "sessionId": BROWSER_SESSION_ID }),
+      "Target.closeTarget" => serde_json::json!({ "success": true }),
+      "Page.addScriptToEvaluateOnNewDocument" => serde_json::json!({ "identifier": "1" }),
+      "Page.getNavigationHistory" => serde_json::json!({
+        "currentIndex": 0,
+        "entries": [{ "id": 1, "url": "about:blank", "title": "" }],
+      }),
+      "Browser.getWindowForTarget" => serde_json::json!({
+        "windowId": 1,
+        "bounds": { "left": 0, "top": 0, "width": 800, "height": 600, "windowState": "normal" },
+      }),
+      // Expressions that happen to be JSON evaluate to themselves, everything else to undefined
+      "Runtime.evaluate" => {
+        match serde_json::from_str::<Value>(cmd.params["expression"].as_str().unwrap_or_default()) {
+          Ok(value) => {
+            serde_json::json!({ "result": { "type": json_type(&value), "value": value } })
+          }
+          Err(_) => serde_json::json!({ "result": { "type": "undefined" } }),
+        }
+      }
+      _ => serde_json::json!({}),
+    };
+
+    Ok(result)
+  }
+}
+
+/// The `RemoteObject` type of a JSON value
+fn json_type(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "object",
+    Value::Bool(_) => "boolean",
+    Value::Number(_) => "number",
+    Value::String(_) => "string",
+    _ => "object",
+  }
+}
+
+/// A `Browser` that launches the fake browser program at `path`, for use with `Window::new_with_browser`.
+/// In integration tests, the path is `env!("CARGO_BIN_EXE_crowser-fake-browser")`.
+pub fn fake_browser(path: &'static str) -> Browser {
+  Browser {
+    name: "fake",
+    kind: BrowserKind::Chromium,
+    win: BrowserWindowsConfig {
+      paths: vec![PathBuf::from(path)],
+      registry_keys: vec![],
+    },
+    unix: vec![path],
+    mac: vec![PathBuf::from(path)],
+  }
+}
+
+/// Keep a fake browser running the way a real one would: until its page is closed or it loses its connection
+pub fn run_until_closed(browser: &FakeBrowser) {
+  // Wait for Crowser to connect in the first place
+  let start = Instant::now();
+
+  while !browser.is_connected() && start.elapsed() < Duration::from_secs(60) {
+    std::thread::sleep(Duration::from_millis(10));
+  }
+
+  while browser.is_connected() {
+    if browser
+      .wait_for("Target.closeTarget", Duration::from_millis(50))
+      .is_some()
+    {
+      // Give the response and events a moment to go out
+      std::thread::sleep(Duration::from_millis(100));
+      return;
+    }
+  }
+}
+
+/// Play back a session recorded with `Window::set_cdp_recording`, as if it were a window that had just been created.
+/// Commands get the responses the browser gave at the time, and its events arrive in between.
+pub fn replay(path: impl AsRef<Path>) -> Result<WindowIpc, CrowserError> {
+  let backend = Arc::new(CdpBackend::replay(path)?);
+  let ipc = BrowserIpc::with_backend(backend, vec![], None, ConsoleHandlers::default(), true)?;
+
+  Ok(WindowIpc::new(Arc::new(Mutex::new(Some(ipc)))))
+}
@@ -0,0 +1,86 @@
+//! Just enough base64 to avoid pulling in another dependency
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+  let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+
+  for chunk in data.chunks(3) {
+    let bytes = [
+      chunk[0],
+      chunk.get(1).copied().unwrap_or(0),
+      chunk.get(2).copied().unwrap_or(0),
+    ];
+    let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(ALPHABET[(n >> (18 - i * 6) & 0x3f) as usize] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+
+  out
+}
+
+/// Decode standard base64, ignoring padding and whitespace. Returns `None` on anything else that isn't base64.
+pub fn decode(data: &str) -> Option<Vec<u8>> {
+  let mut out = Vec::with_capacity(data.len() / 4 * 3);
+  let mut buffer = 0u32;
+  let mut bits = 0;
+
+  for c in data.bytes() {
+    let value = match c {
+      b'A'..=b'Z' => c - b'A',
+      b'a'..=b'z' => c - b'a' + 26,
+      b'0'..=b'9' => c - b'0' + 52,
+      b'+' => 62,
+      b'/' => 63,
+      b'=' | b'\n' | b'\r' | b' ' => continue,
+      _ => return None,
+    } as u32;
+
+    buffer = buffer << 6 | value;
+    bits += 6;
+
+    if bits >= 8 {
+      bits -= 8;
+      out.push((buffer >> bits) as u8);
+      buffer &= (1 << bits) - 1;
+    }
+  }
+
+  Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trips() {
+    let data = (0..=255).collect::<Vec<u8>>();
+
+    for len in 0..data.len() {
+      assert_eq!(decode(&encode(&data[..len])).as_deref(), Some(&data[..len]));
+    }
+  }
+
+  #[test]
+  fn pads_to_whole_quads() {
+    assert_eq!(encode(b""), "");
+    assert_eq!(encode(b"f"), "Zg==");
+    assert_eq!(encode(b"fo"), "Zm8=");
+    assert_eq!(encode(b"foo"), "Zm9v");
+    assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+  }
+
+  #[test]
+  fn decodes_wrapped_and_unpadded_input() {
+    assert_eq!(decode("Zm9v\r\nYmFy").as_deref(), Some(&b"foobar"[..]));
+    assert_eq!(decode("Zm8").as_deref(), Some(&b"fo"[..]));
+    assert_eq!(decode("Zm9v!"), None);
+  }
+}
@@ -0,0 +1,91 @@
+use std::{
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use crate::{
+  error::CrowserError,
+  ipc::{BrowserIpc, DEFAULT_EVAL_TIMEOUT},
+  WindowBounds, WindowState,
+};
+
+/// How often size limits are enforced, since the browser doesn't tell us when the window is resized
+pub const SIZE_LIMIT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often remembered geometry is saved while the window is open, in case the user closes it
+pub const GEOMETRY_SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where the window geometry is saved, relative to the user-provided profile directory
+pub fn geometry_path(profile_directory: &Path) -> PathBuf {
+  profile_directory.join("window_geometry.json")
+}
+
+/// Read the saved geometry, if there is any and it is valid
+pub fn load(profile_directory: &Path) -> Option<WindowBounds> {
+  let contents = std::fs::read_to_string(geometry_path(profile_directory)).ok()?;
+  let bounds: WindowBounds = serde_json::from_str(&contents).ok()?;
+
+  if bounds.width == 0 || bounds.height == 0 {
+    return None;
+  }
+
+  Some(bounds)
+}
+
+pub fn save(profile_directory: &Path, bounds: &WindowBounds) -> Result<(), CrowserError> {
+  std::fs::create_dir_all(profile_directory)?;
+  std::fs::write(
+    geometry_path(profile_directory),
+    serde_json::to_string(bounds).unwrap_or_default(),
+  )?;
+
+  Ok(())
+}
+
+/// Combine the last saved geometry with the window's current bounds.
+/// Position and size are only taken from a normal window, since a maximized or fullscreen window reports the size of the screen.
+/// Minimized windows keep their previous state, so the app doesn't reopen minimized.
+pub fn merge(previous: Option<WindowBounds>, current: WindowBounds) -> WindowBounds {
+  match (previous, current.state) {
+    (_, WindowState::Normal) | (None, _) => current,
+    (Some(previous), WindowState::Minimized) => previous,
+    (Some(previous), state) => WindowBounds { state, ..previous },
+  }
+}
+
+/// The available area of the screen the window is on, as (left, top, width, height)
+pub fn screen_area(ipc: &BrowserIpc) -> Option<(i32, i32, u32, u32)> {
+  let screen = ipc
+    .eval(
+      "[screen.availLeft || 0, screen.availTop || 0, screen.availWidth, screen.availHeight]",
+      DEFAULT_EVAL_TIMEOUT,
+    )
+    .ok()??;
+  let screen: Vec<i64> = serde_json::from_value(screen).ok()?;
+
+  match screen[..] {
+    [left, top, width, height] if width > 0 && height > 0 => {
+      Some((left as i32, top as i32, width as u32, height as u32))
+    }
+    _ => None,
+  }
+}
+
+/// Position a window of the given size in the middle of a screen area
+pub fn center_in(area: (i32, i32, u32, u32), width: u32, height: u32) -> (i32, i32) {
+  let (left, top, area_width, area_height) = area;
+
+  (
+    left + (area_width as i32 - width as i32) / 2,
+    top + (area_height as i32 - height as i32) / 2,
+  )
+}
+
+/// Whether the middle of the window is within the screen area
+pub fn is_on_screen(area: (i32, i32, u32, u32), bounds: &WindowBounds) -> bool {
+  let (left, top, width, height) = area;
+  let x = bounds.x + bounds.width as i32 / 2;
+  let y = bounds.y + bounds.height as i32 / 2;
+
+  x >= left && x < left + width as i32 && y >= top && y < top + height as i32
+}
@@ -1 +1,2 @@
 pub static IPC_JS: &str = include_str!("../../javascript/ipc.js");
+pub static BRANDING_JS: &str = include_str!("../../javascript/branding.js");
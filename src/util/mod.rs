@@ -1,2 +1,5 @@
+pub mod base64;
+pub mod geometry;
 pub mod javascript;
+pub mod open;
 pub mod port;
@@ -0,0 +1,33 @@
+use std::process::Command;
+
+use crate::error::CrowserError;
+
+/// Open a URL in the user's default browser (or mail client, etc.). Only web and mail links are opened,
+/// so pages can't use this to launch local files or arbitrary protocol handlers.
+pub fn open_external(url: &str) -> Result<(), CrowserError> {
+  let scheme = url.split(':').next().unwrap_or_default().to_lowercase();
+
+  if !["http", "https", "mailto"].contains(&scheme.as_str()) {
+    return Err(CrowserError::IoError(std::io::Error::new(
+      std::io::ErrorKind::InvalidInput,
+      format!("Refusing to open {} externally", url),
+    )));
+  }
+
+  #[cfg(target_os = "windows")]
+  let mut cmd = {
+    let mut cmd = Command::new("rundll32");
+    cmd.arg("url.dll,FileProtocolHandler");
+    cmd
+  };
+
+  #[cfg(target_os = "macos")]
+  let mut cmd = Command::new("open");
+
+  #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+  let mut cmd = Command::new("xdg-open");
+
+  cmd.arg(url).spawn()?;
+
+  Ok(())
+}
@@ -31,16 +31,20 @@ impl Webserver {
 
   pub fn poll_request(&self) -> Result<(), CrowserError> {
     if let Ok(Some(request)) = self.server.try_recv() {
-      let mut path = request.url().strip_prefix('/').unwrap_or(request.url());
-
-      // If the path is empty, we should serve the index.html file
-      if path.is_empty() {
-        path = "index.html";
+      let url = request.url();
+      // Query strings and fragments are for the page, not for finding the file
+      let url = url.split(['?', '#']).next().unwrap_or(url);
+      let mut path = url.strip_prefix('/').unwrap_or(url).to_string();
+
+      // If the path is empty or a directory, we should serve its index.html file
+      if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
       }
 
-      let file = self.directory.get_file(path);
+      let file = self.directory.get_file(&path);
 
       if file.is_none() {
+        log::debug!(target: "webserver", "{} not found", url);
         request.respond(Response::empty(404)).unwrap_or_default();
 
         return Ok(());
@@ -48,6 +52,7 @@ impl Webserver {
 
       // Safe to unwrap, we just checked
       let file = file.unwrap();
+      log::trace!(target: "webserver", "Serving {}", url);
       let contents = file.contents_utf8().unwrap_or_default();
       let mime = mime_guess::from_path(file.path()).first_or_octet_stream();
       let mut res = Response::from_string(contents);
@@ -0,0 +1,119 @@
+//! `Window::create` against the fake browser from `crowser::test_support`
+
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crowser::{
+  error::CrowserError,
+  test_support::{fake_browser, replay},
+  RemoteConfig, Window, WindowIpc,
+};
+
+fn window(name: &str) -> Window {
+  let profile_dir =
+    std::env::temp_dir().join(format!("crowser-test-{}-{}", std::process::id(), name));
+  let config = RemoteConfig {
+    url: "about:blank".to_string(),
+  };
+
+  Window::new_with_browser(
+    config,
+    fake_browser(env!("CARGO_BIN_EXE_crowser-fake-browser")),
+    profile_dir,
+  )
+}
+
+/// Like `block_until_initialized`, but gives up instead of hanging the test if `create` failed
+fn wait_until_initialized(ipc: &WindowIpc) {
+  let start = Instant::now();
+
+  while ipc.eval("null").is_err() {
+    assert!(
+      start.elapsed() < Duration::from_secs(10),
+      "Window never initialized"
+    );
+    std::thread::sleep(Duration::from_millis(50));
+  }
+}
+
+fn wait_for_exit(create: std::thread::JoinHandle<Result<(), CrowserError>>) {
+  let start = Instant::now();
+
+  while !create.is_finished() {
+    assert!(
+      start.elapsed() < Duration::from_secs(10),
+      "create() did not return"
+    );
+    std::thread::sleep(Duration::from_millis(50));
+  }
+
+  create.join().unwrap().unwrap();
+}
+
+#[test]
+fn create_attaches_and_evaluates() {
+  let mut window = window("eval");
+  let ipc = window.ipc();
+  let create = std::thread::spawn(move || window.create());
+
+  wait_until_initialized(&ipc);
+
+  assert_eq!(ipc.eval("42").unwrap(), Some(Value::from(42)));
+  assert_eq!(ipc.eval("\"hello\"").unwrap(), Some(Value::from("hello")));
+  assert_eq!(ipc.eval("undefined").unwrap(), None);
+  assert_eq!(ipc.eval_typed::<Vec<u32>>("[1, 2, 3]").unwrap(), [1, 2, 3]);
+  assert_eq!(ipc.eval_typed::<Option<u32>>("undefined").unwrap(), None);
+  assert!(ipc.eval_typed::<u32>("\"not a number\"").is_err());
+  assert_eq!(ipc.current_url().unwrap(), "about:blank");
+
+  ipc.close().unwrap();
+  wait_for_exit(create);
+}
+
+#[test]
+fn close_ends_create() {
+  let mut window = window("close");
+  let ipc = window.ipc();
+  let create = std::thread::spawn(move || window.create());
+
+  wait_until_initialized(&ipc);
+  ipc.close().unwrap();
+
+  wait_for_exit(create);
+
+  // The IPC is gone along with the window
+  assert!(ipc.eval("null").is_err());
+}
+
+#[test]
+fn recorded_session_replays() {
+  let recording = std::env::temp_dir().join(format!(
+    "crowser-test-{}-recording.jsonl",
+    std::process::id()
+  ));
+
+  let mut window = window("recording");
+  window.set_cdp_recording(recording.clone()).unwrap();
+  let ipc = window.ipc();
+  let create = std::thread::spawn(move || window.create());
+
+  wait_until_initialized(&ipc);
+  assert_eq!(
+    ipc.eval("[1, 2, 3]").unwrap(),
+    Some(serde_json::json!([1, 2, 3]))
+  );
+
+  ipc.close().unwrap();
+  wait_for_exit(create);
+
+  // The browser is long gone, but the recording still has its answers
+  let replayed = replay(&recording).unwrap();
+  assert_eq!(
+    replayed.eval("[1, 2, 3]").unwrap(),
+    Some(serde_json::json!([1, 2, 3]))
+  );
+  assert!(replayed.eval("\"not recorded\"").is_err());
+
+  std::fs::remove_file(recording).unwrap_or_default();
+}